@@ -0,0 +1,95 @@
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+
+// Axis-aligned bounding box
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    minimum: Point3,
+    maximum: Point3,
+}
+
+impl Aabb {
+    pub fn new(a: Point3, b: Point3) -> Aabb {
+        Aabb {
+            minimum: Point3::new(
+                f64::min(a.x(), b.x()),
+                f64::min(a.y(), b.y()),
+                f64::min(a.z(), b.z()),
+            ),
+            maximum: Point3::new(
+                f64::max(a.x(), b.x()),
+                f64::max(a.y(), b.y()),
+                f64::max(a.z(), b.z()),
+            ),
+        }
+    }
+
+    pub fn min(&self) -> Point3 {
+        self.minimum
+    }
+
+    pub fn max(&self) -> Point3 {
+        self.maximum
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.minimum + self.maximum)
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.maximum - self.minimum
+    }
+
+    pub fn corners(&self) -> [Point3; 8] {
+        let (a, b) = (self.minimum, self.maximum);
+        [
+            Point3::new(a.x(), a.y(), a.z()),
+            Point3::new(b.x(), a.y(), a.z()),
+            Point3::new(a.x(), b.y(), a.z()),
+            Point3::new(b.x(), b.y(), a.z()),
+            Point3::new(a.x(), a.y(), b.z()),
+            Point3::new(b.x(), a.y(), b.z()),
+            Point3::new(a.x(), b.y(), b.z()),
+            Point3::new(b.x(), b.y(), b.z()),
+        ]
+    }
+
+    // Slab test, returning the entry distance if the ray crosses the box
+    // within [t_min, t_max]
+    pub fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> Option<f64> {
+        for a in 0..3 {
+            let inv_d = 1.0 / r.direction()[a];
+            let mut t0 = (self.minimum[a] - r.origin()[a]) * inv_d;
+            let mut t1 = (self.maximum[a] - r.origin()[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
+}
+
+pub fn surrounding_box(box0: &Aabb, box1: &Aabb) -> Aabb {
+    let small = Point3::new(
+        f64::min(box0.min().x(), box1.min().x()),
+        f64::min(box0.min().y(), box1.min().y()),
+        f64::min(box0.min().z(), box1.min().z()),
+    );
+    let big = Point3::new(
+        f64::max(box0.max().x(), box1.max().x()),
+        f64::max(box0.max().y(), box1.max().y()),
+        f64::max(box0.max().z(), box1.max().z()),
+    );
+
+    Aabb {
+        minimum: small,
+        maximum: big,
+    }
+}
@@ -0,0 +1,143 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// Running mean and variance of a pixel's sample luminance (Welford's method)
+#[derive(Clone, Copy, Default)]
+pub struct PixelStats {
+    pub(crate) samples: u32,
+    pub(crate) mean: f64,
+    pub(crate) m2: f64,
+}
+
+impl PixelStats {
+    pub fn add(&mut self, value: f64) {
+        self.samples += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.samples as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    // Standard error of the pixel mean after the sqrt tone curve applied on
+    // output, so dark and bright pixels are held to the same visible noise
+    pub fn error(&self) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+
+        let variance = self.m2 / (self.samples - 1) as f64;
+        let standard_error = f64::sqrt(variance / self.samples as f64);
+        standard_error / (2.0 * f64::sqrt(f64::max(self.mean, 1.0e-4)))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct AdaptiveSettings {
+    pub min_samples: u32,
+    pub noise_threshold: f64,
+}
+
+impl AdaptiveSettings {
+    // Sampling every pixel up to the full sample count
+    pub fn disabled() -> AdaptiveSettings {
+        AdaptiveSettings {
+            min_samples: 0,
+            noise_threshold: 0.0,
+        }
+    }
+
+    pub fn needs_samples(&self, stats: &PixelStats) -> bool {
+        stats.samples < self.min_samples
+            || self.noise_threshold <= 0.0
+            || stats.error() > self.noise_threshold
+    }
+}
+
+pub struct SampleMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<PixelStats>,
+}
+
+impl SampleMap {
+    pub fn new(width: usize, height: usize) -> SampleMap {
+        SampleMap {
+            width,
+            height,
+            pixels: vec![Default::default(); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> &PixelStats {
+        &self.pixels[y * self.width + x]
+    }
+
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> &mut PixelStats {
+        &mut self.pixels[y * self.width + x]
+    }
+
+    pub(crate) fn pixels(&self) -> &[PixelStats] {
+        &self.pixels
+    }
+
+    pub(crate) fn pixels_mut(&mut self) -> &mut [PixelStats] {
+        &mut self.pixels
+    }
+
+    // True once no pixel needs more samples to reach `samples_per_pixel`
+    pub fn converged(&self, samples_per_pixel: u32, adaptive: &AdaptiveSettings) -> bool {
+        !self
+            .pixels
+            .iter()
+            .any(|p| p.samples < samples_per_pixel && adaptive.needs_samples(p))
+    }
+
+    pub fn total_samples(&self) -> u64 {
+        self.pixels.iter().map(|p| p.samples as u64).sum()
+    }
+
+    // Writes the samples spent per pixel as a PPM heatmap, from blue for the
+    // fewest samples to red for the most
+    pub fn write_heatmap(&self, path: &str) -> io::Result<()> {
+        let file = File::create(path)?;
+        let mut writer = BufWriter::new(file);
+
+        writeln!(writer, "P3\n{} {}\n255", self.width, self.height)?;
+
+        let min = self.pixels.iter().map(|p| p.samples).min().unwrap_or(0);
+        let max = self.pixels.iter().map(|p| p.samples).max().unwrap_or(0);
+        let range = u32::max(max - min, 1) as f64;
+
+        for pixel in &self.pixels {
+            let t = (pixel.samples - min) as f64 / range;
+            let (r, g, b) = heat(t);
+            writeln!(
+                writer,
+                "{} {} {}",
+                (255.0 * r) as i32,
+                (255.0 * g) as i32,
+                (255.0 * b) as i32,
+            )?;
+        }
+
+        writer.flush()
+    }
+}
+
+fn heat(t: f64) -> (f64, f64, f64) {
+    let r = (1.5 - f64::abs(4.0 * t - 3.0)).clamp(0.0, 1.0);
+    let g = (1.5 - f64::abs(4.0 * t - 2.0)).clamp(0.0, 1.0);
+    let b = (1.5 - f64::abs(4.0 * t - 1.0)).clamp(0.0, 1.0);
+    (r, g, b)
+}
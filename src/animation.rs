@@ -0,0 +1,475 @@
+use std::io;
+use std::ops::{Add, Mul, RangeInclusive, Sub};
+use std::sync::Arc;
+
+use crate::aabb::{self, Aabb};
+use crate::bvh::Bvh;
+use crate::camera::{Camera, CameraDescription, Lens, Projection};
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::light::LightList;
+use crate::material::{Dielectric, Lambertian, Material, Metal};
+use crate::ray::Ray;
+use crate::renderer::{Framebuffer, RenderObserver, RenderStatus, Renderer};
+use crate::scene::Scene;
+use crate::transform::{MotionTransformed, Transform, Transformed};
+use crate::vec3::{Point3, Vec3};
+
+// Anything that can be blended linearly, like f64, Vec3 and Color
+pub trait Animatable:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self>
+{
+}
+
+impl<T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>> Animatable for T {}
+
+// How a track moves from a keyframe to the next one
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    // Cubic Bezier through the keyframe handles
+    Bezier,
+    // Smooth curve through the keyframes themselves
+    CatmullRom,
+}
+
+#[derive(Clone)]
+pub struct Keyframe<T> {
+    pub time: f64,
+    pub value: T,
+    pub interpolation: Interpolation,
+    // Incoming and outgoing Bezier control points. Without them, the handles
+    // follow the Catmull-Rom tangent.
+    pub handles: Option<(T, T)>,
+}
+
+// A value keyed over time, in seconds. Before the first and after the last
+// keyframe the track holds still. Tracks start with a keyframe, so they are
+// never empty.
+#[derive(Clone)]
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn new(time: f64, value: T, interpolation: Interpolation) -> Track<T> {
+        Track {
+            keys: vec![Keyframe {
+                time,
+                value,
+                interpolation,
+                handles: None,
+            }],
+        }
+    }
+
+    pub fn constant(value: T) -> Track<T> {
+        Track::new(0.0, value, Interpolation::Linear)
+    }
+
+    pub fn key(self, time: f64, value: T, interpolation: Interpolation) -> Track<T> {
+        self.insert(Keyframe {
+            time,
+            value,
+            interpolation,
+            handles: None,
+        })
+    }
+
+    pub fn bezier_key(self, time: f64, value: T, in_handle: T, out_handle: T) -> Track<T> {
+        self.insert(Keyframe {
+            time,
+            value,
+            interpolation: Interpolation::Bezier,
+            handles: Some((in_handle, out_handle)),
+        })
+    }
+
+    // Keys are kept sorted, and a key at an existing time replaces the old one
+    pub fn insert(mut self, keyframe: Keyframe<T>) -> Track<T> {
+        let index = self.keys.partition_point(|k| k.time < keyframe.time);
+        match self.keys.get(index) {
+            Some(k) if k.time == keyframe.time => self.keys[index] = keyframe,
+            _ => self.keys.insert(index, keyframe),
+        }
+        self
+    }
+
+    pub fn keys(&self) -> &[Keyframe<T>] {
+        &self.keys
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.keys.len() > 1
+    }
+
+    pub fn sample(&self, time: f64) -> T {
+        let first = &self.keys[0];
+        let last = &self.keys[self.keys.len() - 1];
+        if time <= first.time {
+            return first.value;
+        }
+        if time >= last.time {
+            return last.value;
+        }
+
+        let i = self.keys.partition_point(|k| k.time <= time) - 1;
+        let (k0, k1) = (&self.keys[i], &self.keys[i + 1]);
+        let dt = k1.time - k0.time;
+        let u = (time - k0.time) / dt;
+
+        match k0.interpolation {
+            Interpolation::Linear => k0.value + (k1.value - k0.value) * u,
+            Interpolation::CatmullRom => {
+                let m0 = self.tangent(i) * dt;
+                let m1 = self.tangent(i + 1) * dt;
+                let u2 = u * u;
+                let u3 = u2 * u;
+                k0.value * (2.0 * u3 - 3.0 * u2 + 1.0)
+                    + m0 * (u3 - 2.0 * u2 + u)
+                    + k1.value * (3.0 * u2 - 2.0 * u3)
+                    + m1 * (u3 - u2)
+            }
+            Interpolation::Bezier => {
+                let p1 = match k0.handles {
+                    Some((_, out_handle)) => out_handle,
+                    None => k0.value + self.tangent(i) * (dt / 3.0),
+                };
+                let p2 = match k1.handles {
+                    Some((in_handle, _)) => in_handle,
+                    None => k1.value - self.tangent(i + 1) * (dt / 3.0),
+                };
+                let v = 1.0 - u;
+                k0.value * (v * v * v)
+                    + p1 * (3.0 * v * v * u)
+                    + p2 * (3.0 * v * u * u)
+                    + k1.value * (u * u * u)
+            }
+        }
+    }
+
+    // Rate of change at key `i`, from its neighbors' values
+    fn tangent(&self, i: usize) -> T {
+        let prev = &self.keys[i.saturating_sub(1)];
+        let next = &self.keys[usize::min(i + 1, self.keys.len() - 1)];
+        (next.value - prev.value) * (1.0 / (next.time - prev.time))
+    }
+}
+
+pub struct AnimatedCamera {
+    pub projection: Projection,
+    pub lens: Lens,
+    pub lookfrom: Track<Point3>,
+    pub lookat: Track<Point3>,
+    pub vup: Vec3,
+    pub vfov: Track<f64>,
+    pub aspect_ratio: f64,
+    pub aperture: Track<f64>,
+    pub focus_dist: Track<f64>,
+}
+
+impl AnimatedCamera {
+    // Starts as a perspective camera with every parameter held constant
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        vfov: f64,
+        aspect_ratio: f64,
+        aperture: f64,
+        focus_dist: f64,
+    ) -> AnimatedCamera {
+        AnimatedCamera {
+            projection: Projection::Perspective,
+            lens: Lens::default(),
+            lookfrom: Track::constant(lookfrom),
+            lookat: Track::constant(lookat),
+            vup,
+            vfov: Track::constant(vfov),
+            aspect_ratio,
+            aperture: Track::constant(aperture),
+            focus_dist: Track::constant(focus_dist),
+        }
+    }
+
+    pub fn at(&self, time: f64) -> Box<dyn Camera> {
+        CameraDescription {
+            projection: self.projection,
+            lookfrom: self.lookfrom.sample(time),
+            lookat: self.lookat.sample(time),
+            vup: self.vup,
+            vfov: self.vfov.sample(time),
+            aspect_ratio: self.aspect_ratio,
+            aperture: self.aperture.sample(time),
+            focus_dist: self.focus_dist.sample(time),
+            lens_shift: (0.0, 0.0),
+            lens: self.lens.clone(),
+        }
+        .build()
+    }
+}
+
+pub struct AnimatedTransform {
+    pub translation: Track<Vec3>,
+    // Degrees about the x, y and z axes
+    pub rotation: Track<Vec3>,
+    pub scale: Track<f64>,
+}
+
+impl Default for AnimatedTransform {
+    fn default() -> AnimatedTransform {
+        let transform = Transform::default();
+        AnimatedTransform {
+            translation: Track::constant(transform.translation),
+            rotation: Track::constant(transform.rotation),
+            scale: Track::constant(transform.scale),
+        }
+    }
+}
+
+impl AnimatedTransform {
+    pub fn is_animated(&self) -> bool {
+        self.translation.is_animated() || self.rotation.is_animated() || self.scale.is_animated()
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        Transform {
+            translation: self.translation.sample(time),
+            rotation: self.rotation.sample(time),
+            scale: self.scale.sample(time),
+        }
+    }
+}
+
+pub enum AnimatedMaterial {
+    Static(Arc<dyn Material>),
+    Lambertian {
+        albedo: Track<Color>,
+    },
+    Metal {
+        albedo: Track<Color>,
+        fuzz: Track<f64>,
+    },
+    Dielectric {
+        index_of_refraction: Track<f64>,
+    },
+}
+
+impl AnimatedMaterial {
+    pub fn is_animated(&self) -> bool {
+        match self {
+            AnimatedMaterial::Static(_) => false,
+            AnimatedMaterial::Lambertian { albedo } => albedo.is_animated(),
+            AnimatedMaterial::Metal { albedo, fuzz } => albedo.is_animated() || fuzz.is_animated(),
+            AnimatedMaterial::Dielectric {
+                index_of_refraction,
+            } => index_of_refraction.is_animated(),
+        }
+    }
+
+    pub fn at(&self, time: f64) -> Arc<dyn Material> {
+        match self {
+            AnimatedMaterial::Static(material) => material.clone(),
+            AnimatedMaterial::Lambertian { albedo } => {
+                Arc::new(Lambertian::new(albedo.sample(time)))
+            }
+            AnimatedMaterial::Metal { albedo, fuzz } => {
+                Arc::new(Metal::new(albedo.sample(time), fuzz.sample(time)))
+            }
+            AnimatedMaterial::Dielectric {
+                index_of_refraction,
+            } => Arc::new(Dielectric::new(index_of_refraction.sample(time))),
+        }
+    }
+}
+
+type ShapeFn = dyn Fn(Arc<dyn Material>) -> Box<dyn Hittable> + Send + Sync;
+
+// An object rebuilt for each frame from its shape, material and transform
+pub struct AnimatedObject {
+    shape: Box<ShapeFn>,
+    material: AnimatedMaterial,
+    transform: AnimatedTransform,
+}
+
+impl AnimatedObject {
+    // `shape` builds the untransformed object with the given material
+    pub fn new(
+        shape: impl Fn(Arc<dyn Material>) -> Box<dyn Hittable> + Send + Sync + 'static,
+        material: AnimatedMaterial,
+    ) -> AnimatedObject {
+        AnimatedObject {
+            shape: Box::new(shape),
+            material,
+            transform: AnimatedTransform::default(),
+        }
+    }
+
+    pub fn with_transform(mut self, transform: AnimatedTransform) -> AnimatedObject {
+        self.transform = transform;
+        self
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.material.is_animated() || self.transform.is_animated()
+    }
+
+    // The object as the shutter opens at `time`. Moving objects are blurred
+    // along their motion over the `shutter` seconds that follow.
+    pub fn at(&self, time: f64, shutter: f64) -> Box<dyn Hittable> {
+        let object = (self.shape)(self.material.at(time));
+        let transform = self.transform.at(time);
+        if shutter > 0.0 && self.transform.is_animated() {
+            let close = self.transform.at(time + shutter);
+            return Box::new(MotionTransformed::new(object, transform, close, shutter));
+        }
+        if transform.is_identity() {
+            return object;
+        }
+
+        Box::new(Transformed::new(object, transform))
+    }
+}
+
+// A scene whose camera and objects change over time. Static objects share a
+// BVH across all frames; animated ones get a BVH of their own, rebuilt for
+// each frame.
+pub struct Animation {
+    pub camera: AnimatedCamera,
+    statics: Arc<Bvh>,
+    objects: Vec<AnimatedObject>,
+    scene: Scene,
+    built: bool,
+}
+
+impl Animation {
+    pub fn new(camera: AnimatedCamera, statics: HittableList, lights: LightList) -> Animation {
+        let statics = Arc::new(Bvh::from(statics));
+        let world = FrameWorld {
+            statics: statics.clone(),
+            animated: Bvh::new(Vec::new()),
+        };
+
+        Animation {
+            camera,
+            statics,
+            objects: Vec::new(),
+            scene: Scene::new(Box::new(world), lights),
+            built: false,
+        }
+    }
+
+    pub fn with_background_intensity(mut self, intensity: f64) -> Animation {
+        self.scene.background_intensity = intensity;
+        self
+    }
+
+    pub fn add(&mut self, object: AnimatedObject) {
+        self.objects.push(object);
+        self.built = false;
+    }
+
+    // True when only the camera moves, so one scene serves every frame
+    pub fn is_static(&self) -> bool {
+        !self.objects.iter().any(|object| object.is_animated())
+    }
+
+    pub fn scene_at(&mut self, time: f64, shutter: f64) -> &Scene {
+        if !self.built || !self.is_static() {
+            let animated = self
+                .objects
+                .iter()
+                .map(|object| object.at(time, shutter))
+                .collect();
+            self.scene.world = Box::new(FrameWorld {
+                statics: self.statics.clone(),
+                animated: Bvh::new(animated),
+            });
+            self.built = true;
+        }
+
+        &self.scene
+    }
+}
+
+// Static objects keep their IDs, animated objects are numbered after them
+struct FrameWorld {
+    statics: Arc<Bvh>,
+    animated: Bvh,
+}
+
+impl Hittable for FrameWorld {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let statics = self.statics.hit(r, t_min, t_max);
+        let t_max = statics.as_ref().map_or(t_max, |rec| rec.t);
+
+        match self.animated.hit(r, t_min, t_max) {
+            Some(mut rec) => {
+                rec.object_id += self.statics.len();
+                Some(rec)
+            }
+            None => statics,
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match (self.statics.bounding_box(), self.animated.bounding_box()) {
+            (Some(a), Some(b)) => Some(aabb::surrounding_box(&a, &b)),
+            (a, None) if self.animated.is_empty() => a,
+            (None, b) if self.statics.is_empty() => b,
+            _ => None,
+        }
+    }
+}
+
+// Renders frames `first..=last` of an animation, frame `n` showing time
+// `n / fps`
+pub struct SequenceRenderer {
+    renderer: Renderer,
+    frames: RangeInclusive<u32>,
+    fps: f64,
+}
+
+impl SequenceRenderer {
+    pub fn new(renderer: Renderer, frames: RangeInclusive<u32>, fps: f64) -> SequenceRenderer {
+        SequenceRenderer {
+            renderer,
+            frames,
+            fps,
+        }
+    }
+
+    pub fn frames(&self) -> RangeInclusive<u32> {
+        self.frames.clone()
+    }
+
+    // Hands each finished frame to `on_frame` along with its number, and
+    // stops at the first frame that is cancelled or fails to save. Each
+    // frame's samples are seeded from `seed` and the frame number.
+    pub fn render(
+        &self,
+        animation: &mut Animation,
+        seed: u64,
+        observer: &impl RenderObserver,
+        mut on_frame: impl FnMut(u32, Framebuffer) -> io::Result<()>,
+    ) -> io::Result<RenderStatus> {
+        for frame in self.frames() {
+            let time = frame as f64 / self.fps;
+            let camera = animation.camera.at(time);
+            let scene = animation.scene_at(time, self.renderer.shutter());
+
+            let mut state = self.renderer.new_state(seed.wrapping_add(frame as u64));
+            let status = self
+                .renderer
+                .render_state(scene, camera.as_ref(), &mut state, observer);
+            if status == RenderStatus::Cancelled {
+                return Ok(status);
+            }
+
+            on_frame(frame, state.into_framebuffer())?;
+        }
+
+        Ok(RenderStatus::Completed)
+    }
+}
@@ -0,0 +1,225 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::color::Color;
+use crate::vec3::{self, Point3, Vec3};
+
+// First-hit data for a single camera sample. Misses leave the geometric
+// fields zeroed and the object ID unset.
+#[derive(Clone, Copy, Default)]
+pub struct AovSample {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub position: Point3,
+    pub depth: f64,
+    pub object_id: Option<usize>,
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct AovPixel {
+    pub(crate) albedo: Color,
+    pub(crate) normal: Vec3,
+    pub(crate) position: Point3,
+    pub(crate) depth: f64,
+    pub(crate) object_id: Option<usize>,
+    pub(crate) direct: Color,
+    pub(crate) indirect: Color,
+    pub(crate) samples: u32,
+    pub(crate) hits: u32,
+}
+
+impl AovPixel {
+    // The pixel keeps the object ID of its first sample to hit anything
+    pub fn add(&mut self, sample: &AovSample) {
+        if self.object_id.is_none() {
+            self.object_id = sample.object_id;
+        }
+        self.samples += 1;
+
+        self.albedo += sample.albedo;
+        self.direct += sample.direct;
+        self.indirect += sample.indirect;
+
+        if sample.object_id.is_some() {
+            self.hits += 1;
+            self.normal += sample.normal;
+            self.position += sample.position;
+            self.depth += sample.depth;
+        }
+    }
+
+    pub fn merge(&mut self, other: &AovPixel) {
+        if self.object_id.is_none() {
+            self.object_id = other.object_id;
+        }
+        self.samples += other.samples;
+        self.hits += other.hits;
+
+        self.albedo += other.albedo;
+        self.normal += other.normal;
+        self.position += other.position;
+        self.depth += other.depth;
+        self.direct += other.direct;
+        self.indirect += other.indirect;
+    }
+
+    fn scale(&self) -> f64 {
+        1.0 / self.samples.max(1) as f64
+    }
+
+    fn hit_scale(&self) -> f64 {
+        1.0 / self.hits.max(1) as f64
+    }
+
+    pub fn albedo(&self) -> Color {
+        self.albedo * self.scale()
+    }
+
+    // Unit average of the normals at the hits, or zero if nothing was hit
+    pub fn normal(&self) -> Vec3 {
+        if self.normal.near_zero() {
+            Vec3::default()
+        } else {
+            vec3::unit_vector(self.normal)
+        }
+    }
+
+    pub fn position(&self) -> Point3 {
+        self.position * self.hit_scale()
+    }
+
+    // Distance from the camera to the first hit, or zero if nothing was hit
+    pub fn depth(&self) -> f64 {
+        self.depth * self.hit_scale()
+    }
+
+    pub fn object_id(&self) -> Option<usize> {
+        self.object_id
+    }
+
+    pub fn direct(&self) -> Color {
+        self.direct * self.scale()
+    }
+
+    pub fn indirect(&self) -> Color {
+        self.indirect * self.scale()
+    }
+}
+
+pub struct AovBuffers {
+    width: usize,
+    height: usize,
+    pixels: Vec<AovPixel>,
+}
+
+impl AovBuffers {
+    pub fn new(width: usize, height: usize) -> AovBuffers {
+        AovBuffers {
+            width,
+            height,
+            pixels: vec![Default::default(); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Pixels are stored top row first
+    pub fn pixel(&self, x: usize, y: usize) -> &AovPixel {
+        &self.pixels[y * self.width + x]
+    }
+
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> &mut AovPixel {
+        &mut self.pixels[y * self.width + x]
+    }
+
+    pub(crate) fn pixels(&self) -> &[AovPixel] {
+        &self.pixels
+    }
+
+    pub(crate) fn pixels_mut(&mut self) -> &mut [AovPixel] {
+        &mut self.pixels
+    }
+
+    // Writes one PFM image per layer, named `<prefix>.<layer>.pfm`
+    pub fn write(&self, prefix: &str) -> io::Result<()> {
+        self.write_layer(prefix, "albedo", |p| p.albedo())?;
+        self.write_layer(prefix, "normal", |p| p.normal())?;
+        self.write_layer(prefix, "position", |p| p.position())?;
+        self.write_layer(prefix, "direct", |p| p.direct())?;
+        self.write_layer(prefix, "indirect", |p| p.indirect())?;
+        self.write_gray_layer(prefix, "depth", |p| p.depth())?;
+        self.write_gray_layer(prefix, "object_id", |p| {
+            p.object_id().map_or(-1.0, |id| id as f64)
+        })?;
+        Ok(())
+    }
+
+    fn write_layer(
+        &self,
+        prefix: &str,
+        layer: &str,
+        f: impl Fn(&AovPixel) -> Color,
+    ) -> io::Result<()> {
+        let values: Vec<_> = self
+            .pixels
+            .iter()
+            .map(|p| {
+                let c = f(p);
+                [c.x(), c.y(), c.z()]
+            })
+            .collect();
+        write_pfm(
+            &format!("{}.{}.pfm", prefix, layer),
+            self.width,
+            self.height,
+            &values,
+        )
+    }
+
+    fn write_gray_layer(
+        &self,
+        prefix: &str,
+        layer: &str,
+        f: impl Fn(&AovPixel) -> f64,
+    ) -> io::Result<()> {
+        let values: Vec<_> = self.pixels.iter().map(|p| [f(p)]).collect();
+        write_pfm(
+            &format!("{}.{}.pfm", prefix, layer),
+            self.width,
+            self.height,
+            &values,
+        )
+    }
+}
+
+// Portable float map, stored bottom row first
+pub fn write_pfm<const N: usize>(
+    path: &str,
+    width: usize,
+    height: usize,
+    values: &[[f64; N]],
+) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    let magic = if N == 1 { "Pf" } else { "PF" };
+    write!(writer, "{}\n{} {}\n-1.0\n", magic, width, height)?;
+
+    for row in values.chunks(width).rev() {
+        for pixel in row {
+            for channel in pixel {
+                writer.write_all(&(*channel as f32).to_le_bytes())?;
+            }
+        }
+    }
+
+    writer.flush()
+}
@@ -0,0 +1,106 @@
+use std::io;
+use std::sync::Arc;
+
+use crate::color;
+use crate::utils::{self, PI};
+use crate::vec3::{self, Vec3};
+
+// Shape of the lens opening, which is also the shape of out-of-focus
+// highlights. Samples lie within the unit disk, in the lens plane's x and y.
+#[derive(Clone, Default)]
+pub enum ApertureShape {
+    #[default]
+    Circle,
+    // Regular polygon formed by `blades` straight diaphragm blades, rotated
+    // by `rotation` degrees
+    Polygon {
+        blades: u32,
+        rotation: f64,
+    },
+    Image(Arc<ApertureImage>),
+}
+
+impl ApertureShape {
+    pub fn sample(&self) -> Vec3 {
+        match self {
+            ApertureShape::Circle => vec3::random_in_unit_disk(),
+            ApertureShape::Polygon { blades, rotation } => {
+                sample_polygon(u32::max(*blades, 3), *rotation)
+            }
+            ApertureShape::Image(image) => image.sample(),
+        }
+    }
+}
+
+// Picks one of the equal triangles fanning out from the center, then a
+// uniform point within it
+fn sample_polygon(blades: u32, rotation: f64) -> Vec3 {
+    let n = blades as f64;
+    let k = f64::min((utils::random_double() * n).floor(), n - 1.0);
+    let start = utils::degrees_to_radians(rotation) + PI / 2.0;
+    let a0 = start + 2.0 * PI * k / n;
+    let a1 = start + 2.0 * PI * (k + 1.0) / n;
+
+    let su = f64::sqrt(utils::random_double());
+    let r2 = utils::random_double();
+    let (b0, b1) = (su * (1.0 - r2), su * r2);
+
+    Vec3::new(
+        b0 * a0.cos() + b1 * a1.cos(),
+        b0 * a0.sin() + b1 * a1.sin(),
+        0.0,
+    )
+}
+
+// Aperture drawn as an image, where brighter pixels let more light through.
+// The image is centered on the lens and scaled so its longer side spans the
+// aperture diameter.
+pub struct ApertureImage {
+    width: usize,
+    height: usize,
+    // Running total of the pixel weights, for picking pixels by weight
+    cdf: Vec<f64>,
+}
+
+impl ApertureImage {
+    pub fn load(path: &str) -> io::Result<ApertureImage> {
+        let (width, height, pixels) = color::read_ppm(path)?;
+
+        let mut total = 0.0;
+        let cdf: Vec<f64> = pixels
+            .iter()
+            .map(|&p| {
+                total += f64::max(color::luminance(p), 0.0);
+                total
+            })
+            .collect();
+
+        if total <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("aperture image {} is completely black", path),
+            ));
+        }
+
+        Ok(ApertureImage { width, height, cdf })
+    }
+
+    pub fn sample(&self) -> Vec3 {
+        let total = *self.cdf.last().unwrap();
+        let target = utils::random_double() * total;
+        let index = usize::min(
+            self.cdf.partition_point(|&c| c <= target),
+            self.cdf.len() - 1,
+        );
+
+        let x = (index % self.width) as f64 + utils::random_double();
+        let y = (index / self.width) as f64 + utils::random_double();
+        let half = usize::max(self.width, self.height) as f64 / 2.0;
+
+        Vec3::new(
+            (x - self.width as f64 / 2.0) / half,
+            (self.height as f64 / 2.0 - y) / half,
+            0.0,
+        )
+    }
+}
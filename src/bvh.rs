@@ -0,0 +1,194 @@
+use crate::aabb::{self, Aabb};
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::ray::Ray;
+
+const MAX_LEAF_SIZE: usize = 2;
+
+struct BvhNode {
+    bounds: Aabb,
+    // First child for interior nodes, first object for leaves
+    start: usize,
+    // Zero for interior nodes, whose second child follows `start`
+    count: usize,
+}
+
+// Bounding volume hierarchy over a list of objects, stored as a flat array of
+// nodes. Hits report the object's index in the original list as their object
+// ID, like HittableList does. Objects without bounds are tested separately.
+pub struct Bvh {
+    objects: Vec<Box<dyn Hittable>>,
+    ids: Vec<usize>,
+    nodes: Vec<BvhNode>,
+    unbounded: Vec<usize>,
+}
+
+struct BuildItem {
+    id: usize,
+    bounds: Aabb,
+}
+
+impl Bvh {
+    pub fn new(objects: Vec<Box<dyn Hittable>>) -> Bvh {
+        let mut items = Vec::with_capacity(objects.len());
+        let mut unbounded = Vec::new();
+        for (id, object) in objects.iter().enumerate() {
+            match object.bounding_box() {
+                Some(bounds) => items.push(BuildItem { id, bounds }),
+                None => unbounded.push(id),
+            }
+        }
+
+        let mut nodes = Vec::new();
+        if !items.is_empty() {
+            nodes.push(BvhNode {
+                bounds: items[0].bounds,
+                start: 0,
+                count: 0,
+            });
+            build(&mut nodes, 0, &mut items, 0);
+        }
+
+        // Reorder the objects to match the leaves, remembering where each
+        // one came from
+        let ids: Vec<usize> = items.iter().map(|item| item.id).chain(unbounded).collect();
+        let mut slots: Vec<_> = objects.into_iter().map(Some).collect();
+        let objects = ids.iter().map(|&id| slots[id].take().unwrap()).collect();
+        let unbounded = (items.len()..ids.len()).collect();
+
+        Bvh {
+            objects,
+            ids,
+            nodes,
+            unbounded,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+impl From<HittableList> for Bvh {
+    fn from(list: HittableList) -> Bvh {
+        Bvh::new(list.into_objects())
+    }
+}
+
+// Splits `items` at the centroid median along the longest axis of their
+// centroids' bounds, filling in `nodes[node]`. `offset` is the position of
+// `items` within the full item list.
+fn build(nodes: &mut Vec<BvhNode>, node: usize, items: &mut [BuildItem], offset: usize) {
+    let bounds = items[1..].iter().fold(items[0].bounds, |b, item| {
+        aabb::surrounding_box(&b, &item.bounds)
+    });
+    nodes[node].bounds = bounds;
+
+    if items.len() <= MAX_LEAF_SIZE {
+        nodes[node].start = offset;
+        nodes[node].count = items.len();
+        return;
+    }
+
+    let centroid = items[0].bounds.centroid();
+    let centroids = items[1..]
+        .iter()
+        .fold(Aabb::new(centroid, centroid), |b, item| {
+            let c = item.bounds.centroid();
+            aabb::surrounding_box(&b, &Aabb::new(c, c))
+        });
+    let extent = centroids.extent();
+    let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+        0
+    } else if extent.y() > extent.z() {
+        1
+    } else {
+        2
+    };
+
+    let mid = items.len() / 2;
+    items.select_nth_unstable_by(mid, |a, b| {
+        a.bounds.centroid()[axis].total_cmp(&b.bounds.centroid()[axis])
+    });
+
+    let left = nodes.len();
+    for _ in 0..2 {
+        nodes.push(BvhNode {
+            bounds,
+            start: 0,
+            count: 0,
+        });
+    }
+    nodes[node].start = left;
+
+    let (left_items, right_items) = items.split_at_mut(mid);
+    build(nodes, left, left_items, offset);
+    build(nodes, left + 1, right_items, offset + mid);
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut temp_rec = None;
+        let mut closest_so_far = t_max;
+
+        let mut test = |index: usize, closest_so_far: &mut f64| {
+            if let Some(mut rec) = self.objects[index].hit(ray, t_min, *closest_so_far) {
+                rec.object_id = self.ids[index];
+                *closest_so_far = rec.t;
+                temp_rec = Some(rec);
+            }
+        };
+
+        for &index in &self.unbounded {
+            test(index, &mut closest_so_far);
+        }
+
+        if self.nodes.is_empty() {
+            return temp_rec;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if node.bounds.hit(ray, t_min, closest_so_far).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                for index in node.start..node.start + node.count {
+                    test(index, &mut closest_so_far);
+                }
+                continue;
+            }
+
+            // Visit the nearer child first so the farther one can be culled
+            let left = self.nodes[node.start]
+                .bounds
+                .hit(ray, t_min, closest_so_far);
+            let right = self.nodes[node.start + 1]
+                .bounds
+                .hit(ray, t_min, closest_so_far);
+            match (left, right) {
+                (Some(l), Some(r)) if l <= r => stack.extend([node.start + 1, node.start]),
+                (Some(_), Some(_)) => stack.extend([node.start, node.start + 1]),
+                (Some(_), None) => stack.push(node.start),
+                (None, Some(_)) => stack.push(node.start + 1),
+                (None, None) => {}
+            }
+        }
+
+        temp_rec
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+
+        self.nodes.first().map(|node| node.bounds)
+    }
+}
@@ -1,18 +1,75 @@
+use std::str::FromStr;
+
+use crate::aperture::ApertureShape;
 use crate::ray::Ray;
-use crate::utils;
+use crate::utils::{self, PI};
 use crate::vec3::{self, Point3, Vec3};
 
-pub struct Camera {
+pub trait Camera: Send + Sync {
+    // `s` and `t` run from 0 to 1 across the image, starting at the bottom
+    // left corner. Returns None for points the camera does not see, like the
+    // corners outside a fisheye's image circle, or lens samples the barrel
+    // blocks. The renderer counts those samples as black on purpose: they
+    // are the light lost to mechanical vignetting, and they antialias the
+    // edge of a fisheye's circle while pixels wholly outside it stay black.
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray>;
+}
+
+// Orthonormal basis looking from `lookfrom` towards `lookat`, with `w`
+// pointing backwards
+fn camera_basis(lookfrom: Point3, lookat: Point3, vup: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = vec3::unit_vector(lookfrom - lookat);
+    let u = vec3::unit_vector(vec3::cross(vup, w));
+    let v = vec3::cross(w, u);
+    (u, v, w)
+}
+
+// Optical quirks of a real lens beyond its focal length and aperture
+#[derive(Clone)]
+pub struct Lens {
+    pub aperture_shape: ApertureShape,
+    // How much the lens barrel clips the aperture towards the image corners,
+    // turning round bokeh into cat's eyes. 0 disables it, 1 clips the
+    // corners down to a sliver.
+    pub cat_eye: f64,
+    // Anamorphic squeeze, stretching bokeh vertically by this ratio
+    pub anamorphic: f64,
+    // Tilt and swing of the focal plane in degrees. A positive tilt pushes
+    // the top of the plane away from the camera, a positive swing the right.
+    pub tilt: f64,
+    pub swing: f64,
+}
+
+impl Default for Lens {
+    fn default() -> Lens {
+        Lens {
+            aperture_shape: ApertureShape::Circle,
+            cat_eye: 0.0,
+            anamorphic: 1.0,
+            tilt: 0.0,
+            swing: 0.0,
+        }
+    }
+}
+
+// Thin-lens perspective camera
+pub struct PerspectiveCamera {
     origin: Point3,
     lower_left_corner: Point3,
     horizontal: Vec3,
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
+    w: Vec3,
     lens_radius: f64,
+    focus_dist: f64,
+    aspect_ratio: f64,
+    lens: Lens,
+    // Normal of the tilted focal plane, None when it faces the camera
+    focal_plane_normal: Option<Vec3>,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn new(
         lookfrom: Point3,
         lookat: Point3,
@@ -21,15 +78,13 @@ impl Camera {
         aspect_ratio: f64,
         aperture: f64,
         focus_dist: f64,
-    ) -> Camera {
+    ) -> PerspectiveCamera {
         let theta = utils::degrees_to_radians(vfov);
         let h = f64::tan(theta / 2.0);
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
 
-        let w = vec3::unit_vector(lookfrom - lookat);
-        let u = vec3::unit_vector(vec3::cross(vup, w));
-        let v = vec3::cross(w, u);
+        let (u, v, w) = camera_basis(lookfrom, lookat, vup);
 
         let origin = lookfrom;
         let horizontal = focus_dist * viewport_width * u;
@@ -38,24 +93,306 @@ impl Camera {
 
         let lens_radius = aperture / 2.0;
 
-        Camera {
+        PerspectiveCamera {
             origin,
             lower_left_corner,
             horizontal,
             vertical,
             u,
             v,
+            w,
             lens_radius,
+            focus_dist,
+            aspect_ratio,
+            lens: Lens::default(),
+            focal_plane_normal: None,
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * vec3::random_in_unit_disk();
+    pub fn with_lens(mut self, lens: Lens) -> PerspectiveCamera {
+        self.focal_plane_normal = if lens.tilt != 0.0 || lens.swing != 0.0 {
+            let tilt = utils::degrees_to_radians(lens.tilt).tan();
+            let swing = utils::degrees_to_radians(lens.swing).tan();
+            Some(self.w + tilt * self.v + swing * self.u)
+        } else {
+            None
+        };
+        self.lens = lens;
+        self
+    }
+
+    // Slides the image window sideways without turning the camera, in
+    // fractions of the image width and height
+    pub fn with_shift(mut self, shift_x: f64, shift_y: f64) -> PerspectiveCamera {
+        self.lower_left_corner += shift_x * self.horizontal + shift_y * self.vertical;
+        self
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let mut lens_point = self.lens.aperture_shape.sample();
+
+        if self.lens.cat_eye > 0.0 {
+            // The barrel's exit pupil slides towards the image center as the
+            // pixel moves outwards, measured so the corners are 1 away
+            let x = (2.0 * s - 1.0) * self.aspect_ratio;
+            let y = 2.0 * t - 1.0;
+            let scale = self.lens.cat_eye / f64::sqrt(self.aspect_ratio * self.aspect_ratio + 1.0);
+            let pupil = Vec3::new(-x * scale, -y * scale, 0.0);
+            if (lens_point - pupil).length_squared() > 1.0 {
+                return None;
+            }
+        }
+
+        if self.lens.anamorphic != 1.0 {
+            lens_point = Vec3::new(lens_point.x() / self.lens.anamorphic, lens_point.y(), 0.0);
+        }
+
+        let rd = self.lens_radius * lens_point;
         let offset = self.u * rd.x() + self.v * rd.y();
 
-        Ray::new(
+        let mut target = self.lower_left_corner + s * self.horizontal + t * self.vertical;
+        if let Some(normal) = self.focal_plane_normal {
+            // Refocus on where the pinhole ray meets the tilted plane
+            let direction = target - self.origin;
+            let focus_center = self.origin - self.focus_dist * self.w;
+            let along = vec3::dot(direction, normal);
+            let distance = vec3::dot(focus_center - self.origin, normal) / along;
+            if along.abs() > 1.0e-12 && distance > 0.0 {
+                target = self.origin + distance * direction;
+            }
+        }
+
+        Some(Ray::new(
             self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-        )
+            target - self.origin - offset,
+        ))
+    }
+}
+
+// Parallel rays through a `view_height` tall window centered on `lookfrom`
+pub struct OrthographicCamera {
+    lower_left_corner: Point3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    direction: Vec3,
+}
+
+impl OrthographicCamera {
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        view_height: f64,
+        aspect_ratio: f64,
+    ) -> OrthographicCamera {
+        let (u, v, w) = camera_basis(lookfrom, lookat, vup);
+
+        let horizontal = aspect_ratio * view_height * u;
+        let vertical = view_height * v;
+
+        OrthographicCamera {
+            lower_left_corner: lookfrom - horizontal / 2.0 - vertical / 2.0,
+            horizontal,
+            vertical,
+            direction: -w,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        Some(Ray::new(
+            self.lower_left_corner + s * self.horizontal + t * self.vertical,
+            self.direction,
+        ))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FisheyeMapping {
+    // Image radius proportional to the angle off the axis
+    Equidistant,
+    // Preserves solid angles, squeezing the edge of the view more
+    Equisolid,
+}
+
+// Circular fisheye whose image circle spans the image height
+pub struct FisheyeCamera {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    aspect_ratio: f64,
+    half_fov: f64,
+    mapping: FisheyeMapping,
+}
+
+impl FisheyeCamera {
+    // `fov` is the angle across the image circle, in degrees, up to 360
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        fov: f64,
+        aspect_ratio: f64,
+        mapping: FisheyeMapping,
+    ) -> FisheyeCamera {
+        let (u, v, w) = camera_basis(lookfrom, lookat, vup);
+
+        FisheyeCamera {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            aspect_ratio,
+            half_fov: utils::degrees_to_radians(fov.clamp(0.0, 360.0)) / 2.0,
+            mapping,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let x = 2.0 * (s - 0.5) * self.aspect_ratio;
+        let y = 2.0 * (t - 0.5);
+        let r = f64::sqrt(x * x + y * y);
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.half_fov,
+            FisheyeMapping::Equisolid => 2.0 * f64::asin(r * f64::sin(self.half_fov / 2.0)),
+        };
+        let phi = f64::atan2(y, x);
+
+        let direction =
+            theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w;
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+// Full 360x180 degree panorama, with `lookat` in the center of the image.
+// Meant for 2:1 images.
+pub struct EquirectangularCamera {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl EquirectangularCamera {
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3) -> EquirectangularCamera {
+        let (u, v, w) = camera_basis(lookfrom, lookat, vup);
+
+        EquirectangularCamera {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let longitude = (s - 0.5) * 2.0 * PI;
+        let latitude = (t - 0.5) * PI;
+
+        let direction = latitude.cos() * (longitude.sin() * self.u - longitude.cos() * self.w)
+            + latitude.sin() * self.v;
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+    Fisheye { mapping: FisheyeMapping, fov: f64 },
+    Equirectangular,
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Projection, String> {
+        match s {
+            "perspective" => Ok(Projection::Perspective),
+            "orthographic" => Ok(Projection::Orthographic),
+            "fisheye" => Ok(Projection::Fisheye {
+                mapping: FisheyeMapping::Equidistant,
+                fov: 180.0,
+            }),
+            "fisheye-equisolid" => Ok(Projection::Fisheye {
+                mapping: FisheyeMapping::Equisolid,
+                fov: 180.0,
+            }),
+            "equirectangular" => Ok(Projection::Equirectangular),
+            _ => Err(format!("unknown camera '{}'", s)),
+        }
+    }
+}
+
+// Camera placement shared by all projections. The orthographic view covers
+// what the perspective one sees at the focus distance, and the fisheye and
+// equirectangular cameras ignore the lens settings. The lens shift and the
+// lens effects only apply to the perspective camera.
+#[derive(Clone)]
+pub struct CameraDescription {
+    pub projection: Projection,
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub vfov: f64,
+    pub aspect_ratio: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    pub lens_shift: (f64, f64),
+    pub lens: Lens,
+}
+
+impl CameraDescription {
+    pub fn build(&self) -> Box<dyn Camera> {
+        match self.projection {
+            Projection::Perspective => Box::new(
+                PerspectiveCamera::new(
+                    self.lookfrom,
+                    self.lookat,
+                    self.vup,
+                    self.vfov,
+                    self.aspect_ratio,
+                    self.aperture,
+                    self.focus_dist,
+                )
+                .with_shift(self.lens_shift.0, self.lens_shift.1)
+                .with_lens(self.lens.clone()),
+            ),
+            Projection::Orthographic => {
+                let theta = utils::degrees_to_radians(self.vfov);
+                Box::new(OrthographicCamera::new(
+                    self.lookfrom,
+                    self.lookat,
+                    self.vup,
+                    2.0 * self.focus_dist * f64::tan(theta / 2.0),
+                    self.aspect_ratio,
+                ))
+            }
+            Projection::Fisheye { mapping, fov } => Box::new(FisheyeCamera::new(
+                self.lookfrom,
+                self.lookat,
+                self.vup,
+                fov,
+                self.aspect_ratio,
+                mapping,
+            )),
+            Projection::Equirectangular => Box::new(EquirectangularCamera::new(
+                self.lookfrom,
+                self.lookat,
+                self.vup,
+            )),
+        }
     }
 }
@@ -0,0 +1,400 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::Mutex;
+
+use crate::adaptive::{AdaptiveSettings, PixelStats, SampleMap};
+use crate::aov::{AovBuffers, AovPixel};
+use crate::color::Color;
+use crate::exposure::Exposure;
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::renderer::RenderSettings;
+use crate::tile::TileOrder;
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 5;
+// Longest string a checkpoint may hold, so a corrupt length fails cleanly
+// instead of allocating gigabytes
+const MAX_STRING_LEN: usize = 1 << 16;
+
+// Everything needed to continue a render. Camera samples are seeded from
+// `seed`, the pixel and the sample index, so the random number state is fully
+// described by `seed` and the per-pixel sample counts in `stats`.
+pub struct RenderState {
+    pub seed: u64,
+    pub samples_done: u32,
+    // May be raised, and the adaptive settings changed, when resuming
+    pub samples_per_pixel: u32,
+    pub adaptive: AdaptiveSettings,
+    // The settings the render was started with
+    pub settings: RenderSettings,
+    // The application's description of the scene and camera, which must
+    // match for a resumed render to continue the same image
+    pub scene: String,
+    pub film: Mutex<Film>,
+    pub aovs: Mutex<AovBuffers>,
+    pub stats: Mutex<SampleMap>,
+}
+
+impl RenderState {
+    pub fn new(settings: RenderSettings, seed: u64) -> RenderState {
+        let (width, height) = (settings.width, settings.height);
+        RenderState {
+            seed,
+            samples_done: 0,
+            samples_per_pixel: settings.samples_per_pixel,
+            adaptive: settings.adaptive,
+            settings,
+            scene: String::new(),
+            film: Mutex::new(Film::new(width, height, settings.filter)),
+            aovs: Mutex::new(AovBuffers::new(width, height)),
+            stats: Mutex::new(SampleMap::new(width, height)),
+        }
+    }
+
+    // Fails unless the render can be continued with `settings` and `scene`,
+    // naming everything that differs. The sample count and the adaptive
+    // settings are free to change.
+    pub fn check_resumable(&self, settings: &RenderSettings, scene: &str) -> io::Result<()> {
+        let saved = &self.settings;
+        let same_exposure = |a: Option<Exposure>, b: Option<Exposure>| match (a, b) {
+            (Some(a), Some(b)) => {
+                a.iso == b.iso && a.shutter == b.shutter && a.f_number == b.f_number
+            }
+            (a, b) => a.is_none() && b.is_none(),
+        };
+
+        let mismatches: Vec<_> = [
+            (
+                saved.width == settings.width && saved.height == settings.height,
+                format!("resolution {}x{}", saved.width, saved.height),
+            ),
+            (
+                saved.filter.to_string() == settings.filter.to_string()
+                    && saved.filter.radius() == settings.filter.radius(),
+                format!(
+                    "filter {} of radius {}",
+                    saved.filter,
+                    saved.filter.radius()
+                ),
+            ),
+            (
+                saved.samples_per_pass == settings.samples_per_pass,
+                format!("{} samples per pass", saved.samples_per_pass),
+            ),
+            (
+                saved.tile_size == settings.tile_size,
+                format!("tile size {}", saved.tile_size),
+            ),
+            (
+                saved.tile_order == settings.tile_order,
+                format!("tile order {}", saved.tile_order),
+            ),
+            (
+                saved.max_depth == settings.max_depth
+                    && saved.max_diffuse_depth == settings.max_diffuse_depth
+                    && saved.max_specular_depth == settings.max_specular_depth
+                    && saved.max_transmission_depth == settings.max_transmission_depth
+                    && saved.russian_roulette_depth == settings.russian_roulette_depth,
+                "different bounce limits".to_string(),
+            ),
+            (
+                same_exposure(saved.exposure, settings.exposure),
+                format!("exposure {:?}", saved.exposure),
+            ),
+            (
+                saved.white_balance == settings.white_balance,
+                format!("white balance {:?}", saved.white_balance),
+            ),
+            (
+                saved.spectral == settings.spectral,
+                format!("spectral {}", saved.spectral),
+            ),
+            (self.scene == scene, format!("scene {}", self.scene)),
+        ]
+        .into_iter()
+        .filter(|(same, _)| !same)
+        .map(|(_, saved)| saved)
+        .collect();
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid_data(format!(
+                "checkpoint was rendered with {}",
+                mismatches.join("; ")
+            )))
+        }
+    }
+
+    // Writes to a temporary file first so a crash while saving leaves the
+    // previous checkpoint intact
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let temp_path = format!("{}.tmp", path);
+        {
+            let mut out = BufWriter::new(File::create(&temp_path)?);
+            self.write(&mut out)?;
+            out.flush()?;
+        }
+        fs::rename(&temp_path, path)
+    }
+
+    pub fn load(path: &str) -> io::Result<RenderState> {
+        let mut input = BufReader::new(File::open(path)?);
+        RenderState::read(&mut input)
+    }
+
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let film = self.film.lock().unwrap();
+        let aovs = self.aovs.lock().unwrap();
+        let stats = self.stats.lock().unwrap();
+
+        out.write_all(MAGIC)?;
+        write_u32(out, VERSION)?;
+
+        write_u32(out, film.width() as u32)?;
+        write_u32(out, film.height() as u32)?;
+        write_u64(out, self.seed)?;
+        write_u32(out, self.samples_done)?;
+        write_u32(out, self.samples_per_pixel)?;
+        write_u32(out, self.adaptive.min_samples)?;
+        write_f64(out, self.adaptive.noise_threshold)?;
+
+        write_string(out, &film.filter().to_string())?;
+        write_f64(out, film.filter().radius())?;
+
+        let settings = &self.settings;
+        write_u32(out, settings.samples_per_pixel)?;
+        write_u32(out, settings.adaptive.min_samples)?;
+        write_f64(out, settings.adaptive.noise_threshold)?;
+        write_u32(out, settings.samples_per_pass)?;
+        write_u32(out, settings.tile_size as u32)?;
+        write_string(out, &settings.tile_order.to_string())?;
+        write_u32(out, settings.max_depth)?;
+        write_u32(out, settings.max_diffuse_depth)?;
+        write_u32(out, settings.max_specular_depth)?;
+        write_u32(out, settings.max_transmission_depth)?;
+        write_u32(out, settings.russian_roulette_depth)?;
+        write_u32(out, settings.exposure.is_some() as u32)?;
+        let exposure = settings.exposure.unwrap_or_default();
+        write_f64(out, exposure.iso)?;
+        write_f64(out, exposure.shutter)?;
+        write_f64(out, exposure.f_number)?;
+        write_u32(out, settings.white_balance.is_some() as u32)?;
+        write_f64(out, settings.white_balance.unwrap_or_default())?;
+        write_u32(out, settings.spectral as u32)?;
+        write_string(out, &self.scene)?;
+
+        for pixel in film.pixels() {
+            write_color(out, pixel.sum)?;
+            write_f64(out, pixel.weight)?;
+            write_f64(out, pixel.abs_weight)?;
+            write_color(out, pixel.box_sum)?;
+            write_u32(out, pixel.box_count)?;
+        }
+
+        for pixel in aovs.pixels() {
+            write_color(out, pixel.albedo)?;
+            write_color(out, pixel.normal)?;
+            write_color(out, pixel.position)?;
+            write_f64(out, pixel.depth)?;
+            write_u64(out, pixel.object_id.map_or(u64::MAX, |id| id as u64))?;
+            write_color(out, pixel.direct)?;
+            write_color(out, pixel.indirect)?;
+            write_u32(out, pixel.samples)?;
+            write_u32(out, pixel.hits)?;
+        }
+
+        for pixel in stats.pixels() {
+            write_u32(out, pixel.samples)?;
+            write_f64(out, pixel.mean)?;
+            write_f64(out, pixel.m2)?;
+        }
+
+        Ok(())
+    }
+
+    fn read(input: &mut impl Read) -> io::Result<RenderState> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a checkpoint file".to_string()));
+        }
+
+        let version = read_u32(input)?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported checkpoint version {}",
+                version
+            )));
+        }
+
+        let width = read_u32(input)? as usize;
+        let height = read_u32(input)? as usize;
+        let seed = read_u64(input)?;
+        let samples_done = read_u32(input)?;
+        let samples_per_pixel = read_u32(input)?;
+        let adaptive = AdaptiveSettings {
+            min_samples: read_u32(input)?,
+            noise_threshold: read_f64(input)?,
+        };
+
+        let filter: Filter = read_string(input)?.parse().map_err(invalid_data)?;
+        let filter = filter.with_radius(read_f64(input)?);
+
+        let samples_per_pixel_started = read_u32(input)?;
+        let adaptive_started = AdaptiveSettings {
+            min_samples: read_u32(input)?,
+            noise_threshold: read_f64(input)?,
+        };
+        let samples_per_pass = read_u32(input)?;
+        let tile_size = read_u32(input)? as usize;
+        let tile_order: TileOrder = read_string(input)?.parse().map_err(invalid_data)?;
+        let max_depth = read_u32(input)?;
+        let max_diffuse_depth = read_u32(input)?;
+        let max_specular_depth = read_u32(input)?;
+        let max_transmission_depth = read_u32(input)?;
+        let russian_roulette_depth = read_u32(input)?;
+        let has_exposure = read_u32(input)? != 0;
+        let exposure = Exposure {
+            iso: read_f64(input)?,
+            shutter: read_f64(input)?,
+            f_number: read_f64(input)?,
+        };
+        let has_white_balance = read_u32(input)? != 0;
+        let white_balance = read_f64(input)?;
+        let settings = RenderSettings {
+            width,
+            height,
+            samples_per_pixel: samples_per_pixel_started,
+            samples_per_pass,
+            adaptive: adaptive_started,
+            filter,
+            tile_size,
+            tile_order,
+            max_depth,
+            max_diffuse_depth,
+            max_specular_depth,
+            max_transmission_depth,
+            russian_roulette_depth,
+            exposure: has_exposure.then_some(exposure),
+            white_balance: has_white_balance.then_some(white_balance),
+            spectral: read_u32(input)? != 0,
+        };
+        let scene = read_string(input)?;
+
+        let mut film = Film::new(width, height, filter);
+        for pixel in film.pixels_mut() {
+            pixel.sum = read_color(input)?;
+            pixel.weight = read_f64(input)?;
+            pixel.abs_weight = read_f64(input)?;
+            pixel.box_sum = read_color(input)?;
+            pixel.box_count = read_u32(input)?;
+        }
+
+        let mut aovs = AovBuffers::new(width, height);
+        for pixel in aovs.pixels_mut() {
+            *pixel = AovPixel {
+                albedo: read_color(input)?,
+                normal: read_color(input)?,
+                position: read_color(input)?,
+                depth: read_f64(input)?,
+                object_id: match read_u64(input)? {
+                    u64::MAX => None,
+                    id => Some(id as usize),
+                },
+                direct: read_color(input)?,
+                indirect: read_color(input)?,
+                samples: read_u32(input)?,
+                hits: read_u32(input)?,
+            };
+        }
+
+        let mut stats = SampleMap::new(width, height);
+        for pixel in stats.pixels_mut() {
+            *pixel = PixelStats {
+                samples: read_u32(input)?,
+                mean: read_f64(input)?,
+                m2: read_f64(input)?,
+            };
+        }
+
+        Ok(RenderState {
+            seed,
+            samples_done,
+            samples_per_pixel,
+            adaptive,
+            settings,
+            scene,
+            film: Mutex::new(film),
+            aovs: Mutex::new(aovs),
+            stats: Mutex::new(stats),
+        })
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_u32(out: &mut impl Write, value: u32) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_u64(out: &mut impl Write, value: u64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_f64(out: &mut impl Write, value: f64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_string(out: &mut impl Write, value: &str) -> io::Result<()> {
+    write_u32(out, value.len() as u32)?;
+    out.write_all(value.as_bytes())
+}
+
+fn write_color(out: &mut impl Write, value: Color) -> io::Result<()> {
+    write_f64(out, value.x())?;
+    write_f64(out, value.y())?;
+    write_f64(out, value.z())
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64(input: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+fn read_string(input: &mut impl Read) -> io::Result<String> {
+    let len = read_u32(input)? as usize;
+    if len > MAX_STRING_LEN {
+        return Err(invalid_data(format!(
+            "checkpoint string of {} bytes is too long",
+            len
+        )));
+    }
+    let mut bytes = vec![0; len];
+    input.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| invalid_data(e.to_string()))
+}
+
+fn read_color(input: &mut impl Read) -> io::Result<Color> {
+    Ok(Color::new(
+        read_f64(input)?,
+        read_f64(input)?,
+        read_f64(input)?,
+    ))
+}
@@ -1,12 +1,17 @@
 #![allow(unused)]
 
-use std::io::Write;
+use std::fs;
+use std::io::{self, Write};
 
 use crate::utils;
 use crate::vec3::Vec3;
 
 pub type Color = Vec3;
 
+pub fn luminance(color: Color) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
 pub fn write_color(out: &mut impl Write, pixel_color: Color, samples_per_pixel: i32) {
     let mut r = pixel_color.x();
     let mut g = pixel_color.y();
@@ -26,3 +31,93 @@ pub fn write_color(out: &mut impl Write, pixel_color: Color, samples_per_pixel:
     )
     .expect("writing color");
 }
+
+// Packs a color into 0RGB for display, applying the same gamma as write_color
+pub fn color_to_u32(color: Color) -> u32 {
+    let r = color.x().sqrt().clamp(0.0, 0.999);
+    let g = color.y().sqrt().clamp(0.0, 0.999);
+    let b = color.z().sqrt().clamp(0.0, 0.999);
+
+    let ir = (256.0 * r) as u32;
+    let ig = (256.0 * g) as u32;
+    let ib = (256.0 * b) as u32;
+
+    (ir << 16) | (ig << 8) | ib
+}
+
+// Reads a plain (P3) or binary (P6) PPM image into linear colors, undoing the
+// gamma write_color applies. Returns the width, height and pixels, top row
+// first.
+pub fn read_ppm(path: &str) -> io::Result<(usize, usize, Vec<Color>)> {
+    let data = fs::read(path)?;
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    // Header tokens are separated by whitespace, with comments running from
+    // '#' to the end of the line
+    let mut pos = 0;
+    let mut next_token = |pos: &mut usize| -> io::Result<String> {
+        loop {
+            while *pos < data.len() && data[*pos].is_ascii_whitespace() {
+                *pos += 1;
+            }
+            if *pos < data.len() && data[*pos] == b'#' {
+                while *pos < data.len() && data[*pos] != b'\n' {
+                    *pos += 1;
+                }
+                continue;
+            }
+            break;
+        }
+
+        let start = *pos;
+        while *pos < data.len() && !data[*pos].is_ascii_whitespace() {
+            *pos += 1;
+        }
+        if start == *pos {
+            return Err(invalid("unexpected end of PPM file"));
+        }
+        Ok(String::from_utf8_lossy(&data[start..*pos]).into_owned())
+    };
+    let mut next_number = |pos: &mut usize| -> io::Result<usize> {
+        next_token(pos)?
+            .parse()
+            .map_err(|_| invalid("invalid number in PPM file"))
+    };
+
+    let magic = next_token(&mut pos)?;
+    if magic != "P3" && magic != "P6" {
+        return Err(invalid("not a P3 or P6 PPM file"));
+    }
+
+    let width = next_number(&mut pos)?;
+    let height = next_number(&mut pos)?;
+    let max_value = next_number(&mut pos)?;
+    if max_value == 0 || max_value > 255 {
+        return Err(invalid("unsupported PPM maximum value"));
+    }
+
+    let count = 3 * width * height;
+    let values: Vec<usize> = if magic == "P3" {
+        (0..count)
+            .map(|_| next_number(&mut pos))
+            .collect::<io::Result<_>>()?
+    } else {
+        // A single whitespace byte separates the header from the pixels
+        let start = pos + 1;
+        let bytes = data
+            .get(start..start + count)
+            .ok_or_else(|| invalid("truncated PPM file"))?;
+        bytes.iter().map(|&b| b as usize).collect()
+    };
+
+    let decode = |v: usize| {
+        let c = v as f64 / max_value as f64;
+        c * c
+    };
+    let pixels = values
+        .chunks(3)
+        .map(|rgb| Color::new(decode(rgb[0]), decode(rgb[1]), decode(rgb[2])))
+        .collect();
+
+    Ok((width, height, pixels))
+}
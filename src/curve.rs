@@ -0,0 +1,281 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::{self, Point3, Vec3};
+
+// Deepest subdivision of a segment before it is treated as a straight line
+const MAX_SUBDIVISION_DEPTH: i32 = 10;
+
+// A thin fiber swept along a cubic Bezier segment, its width running
+// linearly from `width0` at the first control point to `width1` at the
+// last. It is intersected as a ribbon turned to face each ray and shaded as
+// a cylinder: u runs along the curve, v across it.
+pub struct Curve {
+    control: [Point3; 4],
+    width0: f64,
+    width1: f64,
+    mat: Arc<dyn Material>,
+}
+
+impl Curve {
+    pub fn new(control: [Point3; 4], width0: f64, width1: f64, m: Arc<dyn Material>) -> Curve {
+        Curve {
+            control,
+            width0,
+            width1,
+            mat: m,
+        }
+    }
+
+    fn width(&self, u: f64) -> f64 {
+        (1.0 - u) * self.width0 + u * self.width1
+    }
+}
+
+// Splits a strand through 3n + 1 control points into its n cubic segments,
+// tapering from `root_width` to `tip_width` along its length
+pub fn strand(
+    control: &[Point3],
+    root_width: f64,
+    tip_width: f64,
+    m: Arc<dyn Material>,
+) -> Vec<Curve> {
+    let segments = control.len().saturating_sub(1) / 3;
+    let width = |i: usize| {
+        let s = i as f64 / segments as f64;
+        (1.0 - s) * root_width + s * tip_width
+    };
+
+    (0..segments)
+        .map(|i| {
+            let cp = [
+                control[3 * i],
+                control[3 * i + 1],
+                control[3 * i + 2],
+                control[3 * i + 3],
+            ];
+            Curve::new(cp, width(i), width(i + 1), m.clone())
+        })
+        .collect()
+}
+
+fn lerp(t: f64, a: Vec3, b: Vec3) -> Vec3 {
+    (1.0 - t) * a + t * b
+}
+
+// The point at parameter u along a cubic Bezier, with the derivative there
+fn eval_bezier(cp: &[Vec3; 4], u: f64) -> (Point3, Vec3) {
+    let cp1 = [
+        lerp(u, cp[0], cp[1]),
+        lerp(u, cp[1], cp[2]),
+        lerp(u, cp[2], cp[3]),
+    ];
+    let cp2 = [lerp(u, cp1[0], cp1[1]), lerp(u, cp1[1], cp1[2])];
+    let derivative = if (cp2[1] - cp2[0]).near_zero() {
+        cp[3] - cp[0]
+    } else {
+        3.0 * (cp2[1] - cp2[0])
+    };
+    (lerp(u, cp2[0], cp2[1]), derivative)
+}
+
+// De Casteljau split at u = 1/2, the two halves sharing the middle point
+fn subdivide_bezier(cp: &[Vec3; 4]) -> [[Vec3; 4]; 2] {
+    let mid = |a: Vec3, b: Vec3| 0.5 * (a + b);
+    let p01 = mid(cp[0], cp[1]);
+    let p12 = mid(cp[1], cp[2]);
+    let p23 = mid(cp[2], cp[3]);
+    let p012 = mid(p01, p12);
+    let p123 = mid(p12, p23);
+    let p = mid(p012, p123);
+    [[cp[0], p01, p012, p], [p, p123, p23, cp[3]]]
+}
+
+// Whether a ray-space segment, padded by half its width, can meet the ray
+// running from the origin along z between `z_min` and `z_max`
+fn overlaps_ray(cp: &[Vec3; 4], half_width: f64, z_min: f64, z_max: f64) -> bool {
+    (0..3).all(|axis| {
+        let lo = cp.iter().map(|p| p[axis]).fold(f64::INFINITY, f64::min) - half_width;
+        let hi = cp.iter().map(|p| p[axis]).fold(f64::NEG_INFINITY, f64::max) + half_width;
+        if axis == 2 {
+            hi >= z_min && lo <= z_max
+        } else {
+            hi >= 0.0 && lo <= 0.0
+        }
+    })
+}
+
+// The orthonormal frame a fiber is shaded in: `x` along it, `z` facing back
+// toward the incoming direction and `y` across it
+pub fn fiber_frame(tangent: Vec3, direction: Vec3) -> (Vec3, Vec3) {
+    let facing = -direction - vec3::dot(-direction, tangent) * tangent;
+    let z = if facing.near_zero() {
+        let other = if tangent.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        vec3::unit_vector(vec3::cross(tangent, other))
+    } else {
+        vec3::unit_vector(facing)
+    };
+    (vec3::cross(z, tangent), z)
+}
+
+impl Curve {
+    // Closest crossing of the ray-space segment `cp`, covering [u0, u1] of
+    // the curve, with the ray along z, as (z, u)
+    fn intersect(
+        &self,
+        cp: &[Vec3; 4],
+        u0: f64,
+        u1: f64,
+        depth: i32,
+        z_min: f64,
+        z_max: f64,
+    ) -> Option<(f64, f64)> {
+        if depth > 0 {
+            let halves = subdivide_bezier(cp);
+            let u_mid = 0.5 * (u0 + u1);
+            let mut z_max = z_max;
+            let mut closest = None;
+            for (half, (a, b)) in halves.iter().zip([(u0, u_mid), (u_mid, u1)]) {
+                let half_width = 0.5 * f64::max(self.width(a), self.width(b));
+                if !overlaps_ray(half, half_width, z_min, z_max) {
+                    continue;
+                }
+                if let Some(hit) = self.intersect(half, a, b, depth - 1, z_min, z_max) {
+                    z_max = hit.0;
+                    closest = Some(hit);
+                }
+            }
+            return closest;
+        }
+
+        // The ray must pass between the planes through the segment's end
+        // points perpendicular to its end tangents
+        let edge = (cp[1].y() - cp[0].y()) * -cp[0].y() + cp[0].x() * (cp[0].x() - cp[1].x());
+        if edge < 0.0 {
+            return None;
+        }
+        let edge = (cp[2].y() - cp[3].y()) * -cp[3].y() + cp[3].x() * (cp[3].x() - cp[2].x());
+        if edge < 0.0 {
+            return None;
+        }
+
+        // Treat the segment as a line, finding the point on it closest to
+        // the ray
+        let dx = cp[3].x() - cp[0].x();
+        let dy = cp[3].y() - cp[0].y();
+        let denom = dx * dx + dy * dy;
+        if denom == 0.0 {
+            return None;
+        }
+        let w = (-cp[0].x() * dx - cp[0].y() * dy) / denom;
+        let u = ((1.0 - w) * u0 + w * u1).clamp(u0, u1);
+        let width = self.width(u);
+
+        let (pc, _) = eval_bezier(cp, w.clamp(0.0, 1.0));
+        if pc.x() * pc.x() + pc.y() * pc.y() > 0.25 * width * width {
+            return None;
+        }
+        if pc.z() <= z_min || pc.z() >= z_max {
+            return None;
+        }
+
+        Some((pc.z(), u))
+    }
+}
+
+impl Hittable for Curve {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // Work in a frame with the ray along z from the origin and the
+        // curve running roughly along x
+        let length = r.direction().length();
+        let z = r.direction() / length;
+        let span = self.control[3] - self.control[0];
+        let across = span - vec3::dot(span, z) * z;
+        let (x, y) = if across.near_zero() {
+            fiber_frame(z, Vec3::default())
+        } else {
+            let x = vec3::unit_vector(across);
+            (x, vec3::cross(z, x))
+        };
+        let to_ray = |p: Point3| {
+            let d = p - r.origin();
+            Vec3::new(vec3::dot(d, x), vec3::dot(d, y), vec3::dot(d, z))
+        };
+        let cp = self.control.map(to_ray);
+
+        let z_min = t_min * length;
+        let z_max = t_max * length;
+        let half_width = 0.5 * f64::max(self.width0, self.width1);
+        if !overlaps_ray(&cp, half_width, z_min, z_max) {
+            return None;
+        }
+
+        // Subdivide until the segments are within a twentieth of the width
+        // of straight
+        let flatness = (0..2)
+            .map(|i| {
+                let d = cp[i] - 2.0 * cp[i + 1] + cp[i + 2];
+                f64::max(d.x().abs(), f64::max(d.y().abs(), d.z().abs()))
+            })
+            .fold(0.0, f64::max);
+        let eps = 0.05 * f64::max(self.width0, self.width1);
+        let depth = if flatness > 0.0 {
+            let r0 = f64::log2(std::f64::consts::SQRT_2 * 6.0 * flatness / (8.0 * eps)) / 2.0;
+            (r0.round() as i32).clamp(0, MAX_SUBDIVISION_DEPTH)
+        } else {
+            0
+        };
+
+        let (hit_z, u) = self.intersect(&cp, 0.0, 1.0, depth, z_min, z_max)?;
+        let t = hit_z / length;
+        let p = r.at(t);
+
+        // Shade as the cylinder around the curve, tilting the normal by the
+        // hit's offset across the width
+        let (center, derivative) = eval_bezier(&self.control, u);
+        let tangent = vec3::unit_vector(derivative);
+        let (across, facing) = fiber_frame(tangent, r.direction());
+        let h = (vec3::dot(p - center, across) / (0.5 * self.width(u))).clamp(-1.0, 1.0);
+        let normal = f64::sqrt(1.0 - h * h) * facing + h * across;
+
+        Some(HitRecord {
+            t,
+            p,
+            mat: self.mat.clone(),
+            normal,
+            tangent,
+            u,
+            v: 0.5 * (h + 1.0),
+            front_face: true,
+            object_id: Default::default(),
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // The curve stays within the hull of its control points
+        let half_width = 0.5 * f64::max(self.width0, self.width1);
+        let pad = Vec3::new(half_width, half_width, half_width);
+        let lo = self.control.iter().fold(self.control[0], |acc, &p| {
+            Point3::new(
+                f64::min(acc.x(), p.x()),
+                f64::min(acc.y(), p.y()),
+                f64::min(acc.z(), p.z()),
+            )
+        });
+        let hi = self.control.iter().fold(self.control[0], |acc, &p| {
+            Point3::new(
+                f64::max(acc.x(), p.x()),
+                f64::max(acc.y(), p.y()),
+                f64::max(acc.z(), p.z()),
+            )
+        });
+        Some(Aabb::new(lo - pad, hi + pad))
+    }
+}
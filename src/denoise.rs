@@ -0,0 +1,156 @@
+use rayon::prelude::*;
+
+use crate::aov::AovBuffers;
+use crate::color::Color;
+
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010). Lighting is
+// demodulated by the albedo before filtering so texture detail is kept, and
+// the albedo, normal and depth buffers stop the filter at geometric edges.
+pub struct Denoiser {
+    strength: f64,
+    iterations: u32,
+    sigma_albedo: f64,
+    sigma_normal: f64,
+    sigma_depth: f64,
+}
+
+impl Denoiser {
+    pub fn new(strength: f64) -> Denoiser {
+        Denoiser {
+            strength,
+            iterations: 5,
+            sigma_albedo: 0.1,
+            sigma_normal: 0.3,
+            sigma_depth: 0.05,
+        }
+    }
+
+    pub fn with_iterations(mut self, iterations: u32) -> Denoiser {
+        self.iterations = iterations;
+        self
+    }
+
+    // `color` holds the averaged beauty pass, top row first, matching `aovs`
+    pub fn denoise(&self, color: &[Color], aovs: &AovBuffers) -> Vec<Color> {
+        let width = aovs.width();
+        let height = aovs.height();
+
+        if self.strength <= 0.0 || width == 0 || height == 0 {
+            return color.to_vec();
+        }
+
+        let albedo: Vec<Color> = (0..width * height)
+            .map(|i| aovs.pixel(i % width, i / width).albedo())
+            .collect();
+
+        let mut irradiance: Vec<Color> = color
+            .iter()
+            .zip(&albedo)
+            .map(|(c, a)| demodulate(*c, *a))
+            .collect();
+
+        let mut sigma_color = self.strength;
+        for iteration in 0..self.iterations {
+            irradiance = self.filter_pass(&irradiance, aovs, 1 << iteration, sigma_color);
+            sigma_color *= 0.5;
+        }
+
+        irradiance
+            .iter()
+            .zip(&albedo)
+            .map(|(c, a)| remodulate(*c, *a))
+            .collect()
+    }
+
+    fn filter_pass(
+        &self,
+        input: &[Color],
+        aovs: &AovBuffers,
+        step: usize,
+        sigma_color: f64,
+    ) -> Vec<Color> {
+        let width = aovs.width() as i64;
+        let height = aovs.height() as i64;
+        let step = step as i64;
+
+        let mut output = vec![Color::default(); input.len()];
+
+        output
+            .par_chunks_mut(width as usize)
+            .enumerate()
+            .for_each(|(y, row)| {
+                let y = y as i64;
+                for x in 0..width {
+                    let index = (y * width + x) as usize;
+                    let center = aovs.pixel(x as usize, y as usize);
+                    let c_p = input[index];
+
+                    let mut sum = Color::new(0.0, 0.0, 0.0);
+                    let mut weight_sum = 0.0;
+
+                    for (ky, hy) in KERNEL.iter().enumerate() {
+                        for (kx, hx) in KERNEL.iter().enumerate() {
+                            let qx = x + (kx as i64 - 2) * step;
+                            let qy = y + (ky as i64 - 2) * step;
+                            if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                                continue;
+                            }
+
+                            let q = (qy * width + qx) as usize;
+                            let neighbor = aovs.pixel(qx as usize, qy as usize);
+                            let c_q = input[q];
+
+                            let color_distance = (c_p - c_q).length_squared();
+                            let albedo_distance =
+                                (center.albedo() - neighbor.albedo()).length_squared();
+                            let normal_distance =
+                                (center.normal() - neighbor.normal()).length_squared();
+                            let depth_distance = (center.depth() - neighbor.depth()).abs()
+                                / f64::max(center.depth(), 1.0e-3);
+
+                            let weight = hx
+                                * hy
+                                * f64::exp(
+                                    -color_distance / (sigma_color * sigma_color)
+                                        - albedo_distance / (self.sigma_albedo * self.sigma_albedo)
+                                        - normal_distance / (self.sigma_normal * self.sigma_normal)
+                                        - depth_distance * depth_distance
+                                            / (self.sigma_depth * self.sigma_depth),
+                                );
+
+                            sum += weight * c_q;
+                            weight_sum += weight;
+                        }
+                    }
+
+                    row[x as usize] = if weight_sum > 0.0 {
+                        sum / weight_sum
+                    } else {
+                        c_p
+                    };
+                }
+            });
+
+        output
+    }
+}
+
+fn demodulate(c: Color, albedo: Color) -> Color {
+    const EPS: f64 = 1.0e-3;
+    Color::new(
+        c.x() / f64::max(albedo.x(), EPS),
+        c.y() / f64::max(albedo.y(), EPS),
+        c.z() / f64::max(albedo.z(), EPS),
+    )
+}
+
+fn remodulate(c: Color, albedo: Color) -> Color {
+    const EPS: f64 = 1.0e-3;
+    Color::new(
+        c.x() * f64::max(albedo.x(), EPS),
+        c.y() * f64::max(albedo.y(), EPS),
+        c.z() * f64::max(albedo.z(), EPS),
+    )
+}
@@ -0,0 +1,88 @@
+use crate::color::Color;
+use crate::utils;
+
+// Height of a full frame 35mm sensor, in meters
+pub const FULL_FRAME_SENSOR_HEIGHT: f64 = 0.024;
+
+// Camera settings that turn scene luminance in cd/m² into image values, the
+// way a film camera would. The shutter also sets how long objects move for
+// while a frame is exposed.
+#[derive(Clone, Copy, Debug)]
+pub struct Exposure {
+    pub iso: f64,
+    // Seconds the shutter stays open
+    pub shutter: f64,
+    pub f_number: f64,
+}
+
+impl Default for Exposure {
+    // The sunny 16 rule: f/16 with a shutter of about 1/ISO for a sunlit scene
+    fn default() -> Exposure {
+        Exposure {
+            iso: 100.0,
+            shutter: 1.0 / 125.0,
+            f_number: 16.0,
+        }
+    }
+}
+
+impl Exposure {
+    // Exposure value at ISO 100
+    pub fn ev100(&self) -> f64 {
+        f64::log2(self.f_number * self.f_number / self.shutter * 100.0 / self.iso)
+    }
+
+    // Factor taking luminance to image values, with the luminance that just
+    // saturates the sensor mapping to 1 (ISO 12232's saturation based speed)
+    pub fn scale(&self) -> f64 {
+        1.0 / (1.2 * f64::exp2(self.ev100()))
+    }
+
+    // Diameter of the entrance pupil for a lens of the given focal length
+    pub fn aperture(&self, focal_length: f64) -> f64 {
+        focal_length / self.f_number
+    }
+}
+
+// Focal length giving a vertical field of view of `vfov` degrees on a sensor
+// `sensor_height` tall
+pub fn focal_length(vfov: f64, sensor_height: f64) -> f64 {
+    sensor_height / (2.0 * f64::tan(utils::degrees_to_radians(vfov) / 2.0))
+}
+
+// Per-channel gains that make light from a black body at `temperature`
+// kelvin come out white, keeping its luminance
+pub fn white_balance(temperature: f64) -> Color {
+    let white = blackbody_color(temperature);
+    Color::new(1.0 / white.x(), 1.0 / white.y(), 1.0 / white.z())
+}
+
+// Linear sRGB color of a black body with a luminance of 1, from Kim et al.'s
+// fit of the Planckian locus, which holds from 1667K to 25000K
+pub fn blackbody_color(temperature: f64) -> Color {
+    let t = temperature.clamp(1667.0, 25000.0);
+    let (t1, t2, t3) = (1.0e3 / t, 1.0e6 / (t * t), 1.0e9 / (t * t * t));
+
+    let x = if t <= 4000.0 {
+        -0.2661239 * t3 - 0.2343589 * t2 + 0.8776956 * t1 + 0.179910
+    } else {
+        -3.0258469 * t3 + 2.1070379 * t2 + 0.2226347 * t1 + 0.240390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+    };
+
+    let big_x = x / y;
+    let big_z = (1.0 - x - y) / y;
+    let r = 3.2404542 * big_x - 1.5371385 - 0.4985314 * big_z;
+    let g = -0.9692660 * big_x + 1.8760108 + 0.0415560 * big_z;
+    let b = 0.0556434 * big_x - 0.2040259 + 1.0572252 * big_z;
+
+    // Deep reds fall outside sRGB, so keep every channel slightly positive
+    Color::new(r.max(1.0e-3), g.max(1.0e-3), b.max(1.0e-3))
+}
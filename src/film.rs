@@ -0,0 +1,235 @@
+use crate::color::Color;
+use crate::filter::Filter;
+use crate::tile::Tile;
+
+// Fraction of a pixel's total absolute filter weight its net weight must
+// reach before it is trusted as a normalizer
+const MIN_WEIGHT_FRACTION: f64 = 1.0e-4;
+
+#[derive(Clone, Copy, Default)]
+pub struct FilmPixel {
+    pub sum: Color,
+    pub weight: f64,
+    pub abs_weight: f64,
+    // Unweighted sum and count of the samples taken inside the pixel, for
+    // when negative filter lobes all but cancel out the filtered weight
+    pub box_sum: Color,
+    pub box_count: u32,
+}
+
+impl FilmPixel {
+    pub fn color(&self) -> Color {
+        if self.weight > 0.0 && self.weight > MIN_WEIGHT_FRACTION * self.abs_weight {
+            self.sum / self.weight
+        } else if self.box_count > 0 {
+            self.box_sum / self.box_count as f64
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+// Accumulates filtered samples. Film coordinates have their origin at the
+// top-left corner and pixel (x, y) covers [x, x + 1) x [y, y + 1).
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Filter,
+    pixels: Vec<FilmPixel>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Filter) -> Film {
+        Film {
+            width,
+            height,
+            filter,
+            pixels: vec![Default::default(); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x].color()
+    }
+
+    pub(crate) fn pixels(&self) -> &[FilmPixel] {
+        &self.pixels
+    }
+
+    pub(crate) fn pixels_mut(&mut self) -> &mut [FilmPixel] {
+        &mut self.pixels
+    }
+
+    pub fn image(&self) -> Vec<Color> {
+        self.pixels.iter().map(|p| p.color()).collect()
+    }
+
+    // The colors of the pixels in `bounds`, top row first
+    pub fn resolve(&self, bounds: &Tile) -> Vec<Color> {
+        (bounds.y0..bounds.y1)
+            .flat_map(|y| (bounds.x0..bounds.x1).map(move |x| self.pixel(x, y)))
+            .collect()
+    }
+
+    // Creates a film tile that can receive samples from the pixels of `tile`,
+    // grown by the filter radius so samples can reach pixels owned by
+    // neighboring tiles
+    pub fn tile(&self, tile: &Tile) -> FilmTile {
+        let margin = (self.filter.radius() - 0.5).ceil().max(0.0) as usize;
+        let tile_x0 = tile.x0.saturating_sub(margin);
+        let tile_y0 = tile.y0.saturating_sub(margin);
+        let tile_x1 = usize::min(tile.x1 + margin, self.width);
+        let tile_y1 = usize::min(tile.y1 + margin, self.height);
+
+        FilmTile {
+            index: tile.index,
+            x0: tile_x0,
+            y0: tile_y0,
+            width: tile_x1 - tile_x0,
+            height: tile_y1 - tile_y0,
+            filter: self.filter,
+            pixels: vec![Default::default(); (tile_x1 - tile_x0) * (tile_y1 - tile_y0)],
+        }
+    }
+
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        for ty in 0..tile.height {
+            for tx in 0..tile.width {
+                let src = &tile.pixels[ty * tile.width + tx];
+                let dst = &mut self.pixels[(tile.y0 + ty) * self.width + tile.x0 + tx];
+                dst.sum += src.sum;
+                dst.weight += src.weight;
+                dst.abs_weight += src.abs_weight;
+                dst.box_sum += src.box_sum;
+                dst.box_count += src.box_count;
+            }
+        }
+    }
+}
+
+pub struct FilmTile {
+    index: usize,
+    x0: usize,
+    y0: usize,
+    width: usize,
+    height: usize,
+    filter: Filter,
+    pixels: Vec<FilmPixel>,
+}
+
+impl FilmTile {
+    // Returns the film pixels covered by this tile, margin included
+    pub fn bounds(&self) -> Tile {
+        Tile {
+            index: self.index,
+            x0: self.x0,
+            y0: self.y0,
+            x1: self.x0 + self.width,
+            y1: self.y0 + self.height,
+        }
+    }
+
+    pub fn add_sample(&mut self, film_x: f64, film_y: f64, color: Color) {
+        let (own_x, own_y) = (film_x.floor(), film_y.floor());
+        if own_x >= self.x0 as f64
+            && own_y >= self.y0 as f64
+            && own_x < (self.x0 + self.width) as f64
+            && own_y < (self.y0 + self.height) as f64
+        {
+            let own = (own_y as usize - self.y0) * self.width + own_x as usize - self.x0;
+            self.pixels[own].box_sum += color;
+            self.pixels[own].box_count += 1;
+        }
+
+        let radius = self.filter.radius();
+
+        // Pixels whose centers lie in (film - radius, film + radius]
+        let x_min = f64::max((film_x - 0.5 - radius).floor() + 1.0, self.x0 as f64) as usize;
+        let y_min = f64::max((film_y - 0.5 - radius).floor() + 1.0, self.y0 as f64) as usize;
+        let x_max = f64::min(
+            (film_x - 0.5 + radius).floor() + 1.0,
+            (self.x0 + self.width) as f64,
+        ) as usize;
+        let y_max = f64::min(
+            (film_y - 0.5 + radius).floor() + 1.0,
+            (self.y0 + self.height) as f64,
+        ) as usize;
+
+        for y in y_min..y_max {
+            for x in x_min..x_max {
+                let weight = self
+                    .filter
+                    .evaluate(x as f64 + 0.5 - film_x, y as f64 + 0.5 - film_y);
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let pixel = &mut self.pixels[(y - self.y0) * self.width + x - self.x0];
+                pixel.sum += weight * color;
+                pixel.weight += weight;
+                pixel.abs_weight += weight.abs();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelled_weight_falls_back_to_box_average() {
+        let filter = Filter::lanczos(3.0);
+        let mut film = Film::new(7, 7, filter);
+        let mut tile = film.tile(&Tile {
+            index: 0,
+            x0: 0,
+            y0: 0,
+            x1: 7,
+            y1: 7,
+        });
+
+        // Five samples in the negative lobes around pixel (3, 3), cancelled
+        // by a single sample inside it
+        let negative = [(5.0, 3.5), (5.0, 3.5), (2.0, 3.5), (3.5, 5.0), (3.5, 2.0)];
+        let target: f64 = -negative
+            .iter()
+            .map(|(x, y)| filter.evaluate(x - 3.5, y - 3.5))
+            .sum::<f64>();
+        let (mut lo, mut hi) = (0.0, 0.5);
+        for _ in 0..100 {
+            let mid = 0.5 * (lo + hi);
+            if filter.evaluate(mid, 0.0) > target {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+
+        for (x, y) in negative {
+            tile.add_sample(x, y, Color::new(1.0, 1.0, 1.0));
+        }
+        tile.add_sample(3.5 + lo, 3.5, Color::new(0.5, 0.5, 0.5));
+        film.merge_tile(&tile);
+
+        let pixel = film.pixels()[3 * 7 + 3];
+        assert!(pixel.weight > 0.0 && pixel.weight < 1.0e-6 * pixel.abs_weight);
+
+        let color = film.pixel(3, 3);
+        for i in 0..3 {
+            assert!((color[i] - 0.5).abs() < 1.0e-9, "got {}", color[i]);
+        }
+    }
+}
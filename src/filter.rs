@@ -0,0 +1,141 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::utils::PI;
+
+// Pixel reconstruction filters. Each is separable and evaluated on offsets
+// measured in pixels from the pixel center.
+#[derive(Clone, Copy)]
+pub enum Filter {
+    Box { radius: f64 },
+    Tent { radius: f64 },
+    Gaussian { radius: f64, alpha: f64 },
+    Mitchell { radius: f64, b: f64, c: f64 },
+    Lanczos { radius: f64 },
+}
+
+impl Filter {
+    pub fn box_filter(radius: f64) -> Filter {
+        Filter::Box { radius }
+    }
+
+    pub fn tent(radius: f64) -> Filter {
+        Filter::Tent { radius }
+    }
+
+    pub fn gaussian(radius: f64) -> Filter {
+        Filter::Gaussian { radius, alpha: 2.0 }
+    }
+
+    pub fn mitchell(radius: f64) -> Filter {
+        Filter::Mitchell {
+            radius,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        }
+    }
+
+    pub fn lanczos(radius: f64) -> Filter {
+        Filter::Lanczos { radius }
+    }
+
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius } => radius,
+        }
+    }
+
+    pub fn with_radius(self, radius: f64) -> Filter {
+        match self {
+            Filter::Box { .. } => Filter::Box { radius },
+            Filter::Tent { .. } => Filter::Tent { radius },
+            Filter::Gaussian { alpha, .. } => Filter::Gaussian { radius, alpha },
+            Filter::Mitchell { b, c, .. } => Filter::Mitchell { radius, b, c },
+            Filter::Lanczos { .. } => Filter::Lanczos { radius },
+        }
+    }
+
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        let radius = self.radius();
+        if x > radius {
+            return 0.0;
+        }
+
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { radius } => radius - x,
+            Filter::Gaussian { radius, alpha } => f64::max(
+                0.0,
+                f64::exp(-alpha * x * x) - f64::exp(-alpha * radius * radius),
+            ),
+            Filter::Mitchell { radius, b, c } => mitchell_1d(2.0 * x / radius, b, c),
+            Filter::Lanczos { radius } => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::box_filter(0.5)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Filter, String> {
+        match s {
+            "box" => Ok(Filter::box_filter(0.5)),
+            "tent" => Ok(Filter::tent(1.0)),
+            "gaussian" => Ok(Filter::gaussian(1.5)),
+            "mitchell" => Ok(Filter::mitchell(2.0)),
+            "lanczos" => Ok(Filter::lanczos(3.0)),
+            _ => Err(format!("unknown filter '{}'", s)),
+        }
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let name = match self {
+            Filter::Box { .. } => "box",
+            Filter::Tent { .. } => "tent",
+            Filter::Gaussian { .. } => "gaussian",
+            Filter::Mitchell { .. } => "mitchell",
+            Filter::Lanczos { .. } => "lanczos",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn mitchell_1d(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    if x > 1.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x < 1.0e-5 {
+        return 1.0;
+    }
+    f64::sin(PI * x) / (PI * x)
+}
@@ -1,18 +1,30 @@
 use std::sync::Arc;
 
+use crate::aabb::Aabb;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::utils;
 use crate::vec3::{self, Point3, Vec3};
 
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
+    // Unit direction along the surface in which u increases, for
+    // anisotropic materials
+    pub tangent: Vec3,
     pub mat: Arc<dyn Material>,
     pub t: f64,
+    // Texture coordinates of the hit
+    pub u: f64,
+    pub v: f64,
     pub front_face: bool,
+    pub object_id: usize,
 }
 
 impl HitRecord {
+    // Points the normal against the ray, recording in `front_face` whether
+    // the ray arrived from the outward side so materials can tell the two
+    // sides apart
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3) {
         self.front_face = vec3::dot(r.direction(), outward_normal) < 0.0;
         self.normal = if self.front_face {
@@ -25,4 +37,66 @@ impl HitRecord {
 
 pub trait Hittable: Send + Sync {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+
+    // None for objects without finite bounds
+    fn bounding_box(&self) -> Option<Aabb>;
+}
+
+// An object whose back faces are invisible, so rays from inside or behind it
+// pass through
+pub struct BackfaceCulled {
+    object: Box<dyn Hittable>,
+}
+
+impl BackfaceCulled {
+    pub fn new(object: Box<dyn Hittable>) -> BackfaceCulled {
+        BackfaceCulled { object }
+    }
+}
+
+impl Hittable for BackfaceCulled {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut t_min = t_min;
+        loop {
+            let rec = self.object.hit(r, t_min, t_max)?;
+            if rec.front_face {
+                return Some(rec);
+            }
+            t_min = rec.t;
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+}
+
+// The first hit along `r` that its material's opacity doesn't let through.
+// Partly opaque surfaces stop the ray at random with their opacity as the
+// odds.
+pub fn hit_opaque(world: &dyn Hittable, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let mut t_min = t_min;
+    loop {
+        let rec = world.hit(r, t_min, t_max)?;
+        let opacity = rec.mat.opacity(&rec);
+        if opacity >= 1.0 || opacity > utils::random_double() {
+            return Some(rec);
+        }
+        t_min = rec.t + 0.001;
+    }
+}
+
+// Fraction of light getting through everything along `r` between `t_min`
+// and `t_max`, for shadow rays
+pub fn transmittance(world: &dyn Hittable, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+    let mut t_min = t_min;
+    let mut transmittance = 1.0;
+    while let Some(rec) = world.hit(r, t_min, t_max) {
+        transmittance *= 1.0 - rec.mat.opacity(&rec).clamp(0.0, 1.0);
+        if transmittance <= 0.0 {
+            return 0.0;
+        }
+        t_min = rec.t + 0.001;
+    }
+    transmittance
 }
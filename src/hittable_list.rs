@@ -1,3 +1,4 @@
+use crate::aabb::{self, Aabb};
 use crate::hittable::{HitRecord, Hittable};
 use crate::ray::Ray;
 
@@ -14,6 +15,18 @@ impl HittableList {
     pub fn add(&mut self, object: Box<dyn Hittable>) {
         self.objects.push(object);
     }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn into_objects(self) -> Vec<Box<dyn Hittable>> {
+        self.objects
+    }
 }
 
 impl Hittable for HittableList {
@@ -21,8 +34,9 @@ impl Hittable for HittableList {
         let mut temp_rec = None;
         let mut closest_so_far = t_max;
 
-        for object in &self.objects {
-            if let Some(rec) = object.hit(ray, t_min, closest_so_far) {
+        for (id, object) in self.objects.iter().enumerate() {
+            if let Some(mut rec) = object.hit(ray, t_min, closest_so_far) {
+                rec.object_id = id;
                 closest_so_far = rec.t;
                 temp_rec = Some(rec);
             }
@@ -30,4 +44,15 @@ impl Hittable for HittableList {
 
         temp_rec
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut objects = self.objects.iter();
+        let mut output_box = objects.next()?.bounding_box()?;
+
+        for object in objects {
+            output_box = aabb::surrounding_box(&output_box, &object.bounding_box()?);
+        }
+
+        Some(output_box)
+    }
 }
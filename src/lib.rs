@@ -1,9 +1,30 @@
+pub mod aabb;
+pub mod adaptive;
+pub mod animation;
+pub mod aov;
+pub mod aperture;
+pub mod bvh;
 pub mod camera;
+pub mod checkpoint;
 pub mod color;
+pub mod curve;
+pub mod denoise;
+pub mod exposure;
+pub mod film;
+pub mod filter;
 pub mod hittable;
 pub mod hittable_list;
+pub mod light;
 pub mod material;
+pub mod mtl;
 pub mod ray;
+pub mod renderer;
+pub mod scene;
+pub mod spectrum;
 pub mod sphere;
+pub mod stereo;
+pub mod texture;
+pub mod tile;
+pub mod transform;
 pub mod utils;
 pub mod vec3;
@@ -0,0 +1,196 @@
+use crate::color::Color;
+use crate::hittable::{self, HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::utils::{self, PI};
+use crate::vec3::{self, Point3, Vec3};
+
+// Incident light at a shading point. `li` is irradiance arriving perpendicular
+// to `direction` in W/m², so shading only needs to multiply by the BRDF and cosine.
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f64,
+    pub li: Color,
+}
+
+pub trait Light: Send + Sync {
+    fn sample(&self, p: Point3) -> Option<LightSample>;
+}
+
+pub struct PointLight {
+    position: Point3,
+    intensity: Color,
+}
+
+impl PointLight {
+    // Intensity in W/sr
+    pub fn new(position: Point3, intensity: Color) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+
+    // Total emitted power in W
+    pub fn from_power(position: Point3, power: Color) -> PointLight {
+        PointLight::new(position, power / (4.0 * PI))
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            li: self.intensity / distance_squared,
+        })
+    }
+}
+
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3,
+    intensity: Color,
+    cos_total_width: f64,
+    cos_falloff_start: f64,
+}
+
+impl SpotLight {
+    // Intensity in W/sr along the axis; angles are half-angles in degrees
+    pub fn new(
+        position: Point3,
+        target: Point3,
+        intensity: Color,
+        total_width: f64,
+        falloff_start: f64,
+    ) -> SpotLight {
+        SpotLight {
+            position,
+            direction: vec3::unit_vector(target - position),
+            intensity,
+            cos_total_width: f64::cos(utils::degrees_to_radians(total_width)),
+            cos_falloff_start: f64::cos(utils::degrees_to_radians(falloff_start)),
+        }
+    }
+
+    // Total emitted power in W
+    pub fn from_power(
+        position: Point3,
+        target: Point3,
+        power: Color,
+        total_width: f64,
+        falloff_start: f64,
+    ) -> SpotLight {
+        let mut light = SpotLight::new(position, target, power, total_width, falloff_start);
+        let solid_angle =
+            2.0 * PI * (1.0 - 0.5 * (light.cos_falloff_start + light.cos_total_width));
+        light.intensity = power / solid_angle;
+        light
+    }
+
+    fn falloff(&self, w: Vec3) -> f64 {
+        let cos_theta = vec3::dot(w, self.direction);
+        if cos_theta < self.cos_total_width {
+            return 0.0;
+        }
+        if cos_theta >= self.cos_falloff_start {
+            return 1.0;
+        }
+
+        let delta =
+            (cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width);
+        delta * delta * (3.0 - 2.0 * delta)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        let direction = to_light / distance;
+
+        let falloff = self.falloff(-direction);
+        if falloff <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            li: falloff * self.intensity / distance_squared,
+        })
+    }
+}
+
+pub struct DirectionalLight {
+    direction: Vec3,
+    irradiance: Color,
+}
+
+impl DirectionalLight {
+    // `direction` is the direction the light travels in; irradiance in W/m²
+    pub fn new(direction: Vec3, irradiance: Color) -> DirectionalLight {
+        DirectionalLight {
+            direction: -vec3::unit_vector(direction),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: Point3) -> Option<LightSample> {
+        Some(LightSample {
+            direction: self.direction,
+            distance: f64::INFINITY,
+            li: self.irradiance,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct LightList {
+    lights: Vec<Box<dyn Light>>,
+}
+
+impl LightList {
+    pub fn new() -> LightList {
+        Default::default()
+    }
+
+    pub fn add(&mut self, light: Box<dyn Light>) {
+        self.lights.push(light);
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    pub fn direct_lighting(&self, world: &dyn Hittable, r_in: &Ray, rec: &HitRecord) -> Color {
+        let mut total = Color::new(0.0, 0.0, 0.0);
+
+        for light in &self.lights {
+            let Some(sample) = light.sample(rec.p) else {
+                continue;
+            };
+
+            // Partly opaque occluders let some of the light through
+            let shadow_ray = r_in.spawn(rec.p, sample.direction);
+            let visibility = hittable::transmittance(world, &shadow_ray, 0.001, sample.distance);
+            if visibility <= 0.0 {
+                continue;
+            }
+
+            total += visibility * rec.mat.eval(r_in, rec, &shadow_ray) * sample.li;
+        }
+
+        total
+    }
+}
@@ -1,55 +1,77 @@
 use raytracer as rt;
 
 use rt::{
-    camera::Camera,
+    adaptive::{AdaptiveSettings, SampleMap},
+    animation::{AnimatedCamera, Animation, Interpolation, SequenceRenderer, Track},
+    aov::AovBuffers,
+    aperture::{ApertureImage, ApertureShape},
+    bvh::Bvh,
+    camera::{CameraDescription, Lens, Projection},
+    checkpoint::RenderState,
     color::Color,
-    hittable::Hittable,
+    curve,
+    denoise::Denoiser,
+    exposure::{self, Exposure},
+    filter::Filter,
     hittable_list::HittableList,
-    material::{Dielectric, Lambertian, Metal},
-    ray::Ray,
+    light::{DirectionalLight, LightList, PointLight},
+    material::{
+        AnisotropicMetal, ClearCoat, Dielectric, DiffuseModel, Dispersion, DispersiveDielectric,
+        FilmBase, Hair, Lambertian, Masked, Material, Metal, Mix, Subsurface, ThinFilm, ThinSheet,
+        TwoSided,
+    },
+    renderer::{
+        CancellationToken, RenderObserver, RenderProgress, RenderSettings, RenderStatus, Renderer,
+    },
+    scene::Scene,
     sphere::Sphere,
-    vec3::Point3,
+    stereo::{self, Convergence, Eye, StereoLayout, StereoRig},
+    texture::{CheckerTexture, NoiseTexture},
+    tile::{Tile, TileOrder},
+    vec3::{self, Point3, Vec3},
 };
 
-use rt::{color, utils, vec3};
+use rt::{color, utils};
 
 use indicatif::{ProgressBar, ProgressStyle};
 use minifb::{Key, Window, WindowOptions};
-use rayon::prelude::*;
 use std::fs::File;
 use std::io::{BufWriter, Write};
-use std::sync::Arc;
+use std::ops::RangeInclusive;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 const ASPECT_RATIO: f64 = 16.0 / 9.0;
 const IMAGE_WIDTH: i32 = 800;
 const IMAGE_HEIGHT: i32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as i32;
-const SAMPLES_PER_PIXEL: i32 = 100;
-const MAX_DEPTH: i32 = 50;
-const BUFFER_SIZE: usize = (IMAGE_WIDTH * IMAGE_HEIGHT) as usize;
+const SAMPLES_PER_PIXEL: u32 = 100;
+const DEFAULT_MIN_SAMPLES: u32 = 16;
+// Equirectangular panoramas cover 360 by 180 degrees, so they are twice as
+// wide as they are tall
+const PANORAMA_HEIGHT: i32 = IMAGE_WIDTH / 2;
 
 const DISPLAY_IN_WINDOW: bool = true;
+const DEFAULT_DENOISE_STRENGTH: f64 = 0.5;
+const DEFAULT_TILE_SIZE: usize = 32;
+const DEFAULT_CHECKPOINT_INTERVAL: u64 = 60;
+const DEFAULT_FPS: f64 = 24.0;
+const DEFAULT_APERTURE: f64 = 0.1;
+const DEFAULT_VFOV: f64 = 20.0;
+const DEFAULT_INTEROCULAR: f64 = 0.065;
+const DEFAULT_ROUGHNESS: f64 = 0.5;
+const ORBIT_SECONDS: f64 = 8.0;
+const FUR_STRANDS: usize = 5000;
+// Luminance of the white sky in cd/m² when rendering with a physical
+// exposure, so the sunny 16 rule exposes the scene like the default render
+const DAYLIGHT_LUMINANCE: f64 = 30000.0;
 
-fn ray_color(r: &Ray, world: &dyn Hittable, depth: i32) -> Color {
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
-    }
-
-    if let Some(hit_rec) = world.hit(r, 0.001, f64::INFINITY) {
-        if let Some(scatter_rec) = hit_rec.mat.scatter(r, &hit_rec) {
-            return scatter_rec.attenuation * ray_color(&scatter_rec.scattered, world, depth - 1);
-        }
-        return Color::new(0.0, 0.0, 0.0);
-    }
-
-    let unit_direction = vec3::unit_vector(r.direction());
-    let t = 0.5 * (unit_direction.y() + 1.0);
-    (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
-}
-
-fn random_scene() -> HittableList {
+fn random_scene(options: &Options) -> HittableList {
     let mut world = HittableList::new();
 
-    let ground_material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let ground_material = options
+        .diffuse
+        .material(Color::new(0.5, 0.5, 0.5), options.roughness);
     world.add(Box::new(Sphere::new(
         Point3::new(0.0, -1000.0, 0.0),
         1000.0,
@@ -69,7 +91,11 @@ fn random_scene() -> HittableList {
                 if choose_mat < 0.8 {
                     // Diffuse
                     let albedo = Color::random() * Color::random();
-                    let sphere_material = Arc::new(Lambertian::new(albedo));
+                    let sphere_material: Arc<dyn Material> = if options.layered {
+                        Arc::new(ClearCoat::car_paint(albedo, 0.3))
+                    } else {
+                        options.diffuse.material(albedo, options.roughness)
+                    };
                     world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
                 } else if choose_mat < 0.95 {
                     // Metal
@@ -77,6 +103,10 @@ fn random_scene() -> HittableList {
                     let fuzz = utils::random_double_range(0.0, 0.5);
                     let sphere_material = Arc::new(Metal::new(albedo, fuzz));
                     world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
+                } else if let Some(thickness) = options.thin_film {
+                    // Soap bubble
+                    let sphere_material = Arc::new(ThinFilm::bubble(thickness));
+                    world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
                 } else {
                     // Glass
                     let sphere_material = Arc::new(Dielectric::new(1.5));
@@ -86,21 +116,95 @@ fn random_scene() -> HittableList {
         }
     }
 
-    let material1 = Arc::new(Dielectric::new(1.5));
+    let material1: Arc<dyn Material> = match options.glass {
+        Some(dispersion) => Arc::new(DispersiveDielectric::new(dispersion)),
+        None => Arc::new(Dielectric::new(1.5)),
+    };
     world.add(Box::new(Sphere::new(
         Point3::new(0.0, 1.0, 0.0),
         1.0,
         material1,
     )));
 
-    let material2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
+    let albedo2 = Color::new(0.4, 0.2, 0.1);
+    let material2: Arc<dyn Material> = if options.lampshade {
+        // Tan paper outside and whiter inside, lit by a bulb at its center
+        Arc::new(TwoSided::new(
+            Arc::new(ThinSheet::new(
+                Color::new(0.55, 0.4, 0.25),
+                Color::new(0.35, 0.25, 0.12),
+            )),
+            Arc::new(ThinSheet::new(
+                Color::new(0.7, 0.65, 0.6),
+                Color::new(0.25, 0.2, 0.15),
+            )),
+        ))
+    } else if options.subsurface {
+        Arc::new(Subsurface::wax())
+    } else if options.layered {
+        // Varnished wood, its grain darker where the noise is strong
+        let grain = Mix::textured(
+            Arc::new(Lambertian::new(albedo2)),
+            Arc::new(Lambertian::new(0.4 * albedo2)),
+            Arc::new(NoiseTexture::new(4.0)),
+        );
+        Arc::new(ClearCoat::varnish(Arc::new(grain)))
+    } else {
+        options.diffuse.material(albedo2, options.roughness)
+    };
+    let material2: Arc<dyn Material> = if options.alpha_mask {
+        // Mostly clear squares cut out of the surface
+        let mask = CheckerTexture::new(0.25, Color::new(1.0, 1.0, 1.0), Color::new(0.2, 0.2, 0.2));
+        Arc::new(Masked::new(material2, Arc::new(mask)))
+    } else {
+        material2
+    };
     world.add(Box::new(Sphere::new(
         Point3::new(-4.0, 1.0, 0.0),
         1.0,
         material2,
     )));
 
-    let material3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+    if options.hair {
+        // Strands rooted just under the surface, drooping under their own
+        // weight and tapering toward the tips
+        let fur: Arc<dyn Material> = Arc::new(Hair::new(1.3, 0.0));
+        let center = Point3::new(-4.0, 1.0, 0.0);
+        for _ in 0..FUR_STRANDS {
+            let normal = vec3::random_unit_vector();
+            let root = center + 0.98 * normal;
+            let length = utils::random_double_range(0.2, 0.3);
+            let droop = length * (Vec3::new(0.0, -0.5, 0.0) + 0.3 * vec3::random_in_unit_sphere());
+            let control = [
+                root,
+                root + length / 3.0 * normal,
+                root + 2.0 * length / 3.0 * normal + 0.5 * droop,
+                root + length * normal + droop,
+            ];
+            for segment in curve::strand(&control, 0.01, 0.002, fur.clone()) {
+                world.add(Box::new(segment));
+            }
+        }
+    }
+
+    let albedo3 = Color::new(0.7, 0.6, 0.5);
+    let material3: Arc<dyn Material> = match options.thin_film {
+        // An oxide layer of uneven thickness
+        Some(thickness) => Arc::new(
+            ThinFilm::new(
+                thickness,
+                2.0,
+                FilmBase::Metal {
+                    albedo: albedo3,
+                    fuzz: 0.0,
+                },
+            )
+            .with_thickness_map(Arc::new(NoiseTexture::new(2.0))),
+        ),
+        // Brushed around its vertical axis
+        None if options.brushed => Arc::new(AnisotropicMetal::new(albedo3, 0.05, 0.35)),
+        None => Arc::new(Metal::new(albedo3, 0.0)),
+    };
     world.add(Box::new(Sphere::new(
         Point3::new(4.0, 1.0, 0.0),
         1.0,
@@ -110,25 +214,280 @@ fn random_scene() -> HittableList {
     world
 }
 
-fn color_to_u32(color: Color, samples_per_pixel: i32) -> u32 {
-    let scale = 1.0 / samples_per_pixel as f64;
-    let r = (color.x() * scale).sqrt().clamp(0.0, 0.999);
-    let g = (color.y() * scale).sqrt().clamp(0.0, 0.999);
-    let b = (color.z() * scale).sqrt().clamp(0.0, 0.999);
+// Brightness of the sky and lights, in physical units when the camera has an
+// exposure
+fn scene_intensity(options: &Options) -> f64 {
+    match options.exposure {
+        Some(_) => DAYLIGHT_LUMINANCE,
+        None => 1.0,
+    }
+}
+
+fn scene_lights(options: &Options, intensity: f64) -> LightList {
+    let mut lights = LightList::new();
+
+    // Low sun from behind the camera, plus a warm fill above the big spheres
+    lights.add(Box::new(DirectionalLight::new(
+        Vec3::new(-1.0, -1.0, -0.3),
+        intensity * Color::new(0.6, 0.57, 0.5),
+    )));
+    lights.add(Box::new(PointLight::from_power(
+        Point3::new(0.0, 6.0, 2.0),
+        intensity * Color::new(150.0, 125.0, 100.0),
+    )));
+    if options.lampshade {
+        lights.add(Box::new(PointLight::from_power(
+            Point3::new(-4.0, 1.0, 0.0),
+            intensity * Color::new(30.0, 24.0, 15.0),
+        )));
+    }
 
-    let ir = (256.0 * r) as u32;
-    let ig = (256.0 * g) as u32;
-    let ib = (256.0 * b) as u32;
+    lights
+}
+
+fn scene_camera(options: &Options) -> CameraDescription {
+    let mut camera = CameraDescription {
+        projection: options.projection,
+        lookfrom: Point3::new(13.0, 2.0, 3.0),
+        lookat: Point3::new(0.0, 0.0, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        vfov: options.vfov,
+        aspect_ratio: options.width as f64 / options.height as f64,
+        aperture: options.aperture,
+        focus_dist: 10.0,
+        lens_shift: (0.0, 0.0),
+        lens: options.lens.clone(),
+    };
+
+    // The scene is laid out in meters and shot on a full frame sensor
+    if let Some(exposure) = options.exposure {
+        let focal_length = exposure::focal_length(camera.vfov, exposure::FULL_FRAME_SENSOR_HEIGHT);
+        camera.aperture = exposure.aperture(focal_length);
+    }
 
-    (ir << 16) | (ig << 8) | ib
+    camera
+}
+
+fn build_scene(options: &Options) -> Scene {
+    let intensity = scene_intensity(options);
+    let mut scene = Scene::new(
+        Box::new(Bvh::from(random_scene(options))),
+        scene_lights(options, intensity),
+    );
+    scene.background_intensity = intensity;
+    scene
 }
 
-fn render_to_window() {
-    let mut buffer: Vec<u32> = vec![0; BUFFER_SIZE];
+// Orbits the camera once around the scene, keeping everything else still so
+// every frame shares one BVH
+fn scene_animation(options: &Options) -> Animation {
+    let still = scene_camera(options);
+    let mut camera = AnimatedCamera::new(
+        still.lookfrom,
+        still.lookat,
+        still.vup,
+        still.vfov,
+        still.aspect_ratio,
+        still.aperture,
+        still.focus_dist,
+    );
+    camera.projection = still.projection;
+    camera.lens = still.lens;
+
+    let radius = f64::sqrt(13.0 * 13.0 + 3.0 * 3.0);
+    let start = f64::atan2(3.0, 13.0);
+    let orbit = |i: u32| {
+        let angle = start + i as f64 * utils::PI / 4.0;
+        Point3::new(radius * angle.cos(), 2.0, radius * angle.sin())
+    };
+    let mut lookfrom = Track::new(0.0, orbit(0), Interpolation::CatmullRom);
+    for i in 1..=8 {
+        lookfrom = lookfrom.key(
+            i as f64 * ORBIT_SECONDS / 8.0,
+            orbit(i),
+            Interpolation::CatmullRom,
+        );
+    }
+    camera.lookfrom = lookfrom;
+
+    let intensity = scene_intensity(options);
+    Animation::new(
+        camera,
+        random_scene(options),
+        scene_lights(options, intensity),
+    )
+    .with_background_intensity(intensity)
+}
+
+// Forwards finished tiles to `on_tile`, reports progress on `bar` and saves
+// checkpoints between passes. A failed save cancels the render and is
+// reported once it returns.
+struct CliObserver<'a, F> {
+    checkpoint: Option<&'a str>,
+    checkpoint_interval: Duration,
+    last_checkpoint: Mutex<Instant>,
+    error: Mutex<Option<std::io::Error>>,
+    cancel: CancellationToken,
+    bar: Option<ProgressBar>,
+    on_tile: F,
+}
+
+impl<'a, F: Fn(Tile, &[Color]) + Sync> CliObserver<'a, F> {
+    fn new(options: &'a Options, cancel: CancellationToken, on_tile: F) -> CliObserver<'a, F> {
+        CliObserver {
+            checkpoint: options.checkpoint_path(),
+            checkpoint_interval: options.checkpoint_interval,
+            last_checkpoint: Mutex::new(Instant::now()),
+            error: Mutex::new(None),
+            cancel,
+            bar: None,
+            on_tile,
+        }
+    }
+
+    fn with_progress_bar(mut self, bar: ProgressBar) -> CliObserver<'a, F> {
+        self.bar = Some(bar);
+        self
+    }
+
+    fn finish(self) -> std::io::Result<()> {
+        match self.error.into_inner().unwrap() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<F: Fn(Tile, &[Color]) + Sync> RenderObserver for CliObserver<'_, F> {
+    fn tile_finished(&self, bounds: Tile, pixels: &[Color]) {
+        (self.on_tile)(bounds, pixels);
+    }
+
+    fn pass_finished(&self, state: &RenderState) {
+        let Some(path) = self.checkpoint else {
+            return;
+        };
+
+        let mut last_checkpoint = self.last_checkpoint.lock().unwrap();
+        let finished = state.samples_done == state.samples_per_pixel;
+        if finished || last_checkpoint.elapsed() >= self.checkpoint_interval {
+            if let Err(error) = state.save(path) {
+                *self.error.lock().unwrap() = Some(error);
+            }
+            *last_checkpoint = Instant::now();
+        }
+    }
+
+    fn progress(&self, progress: &RenderProgress) {
+        let Some(bar) = &self.bar else {
+            return;
+        };
+
+        bar.set_length(progress.total_tiles as u64);
+        bar.set_position(progress.tiles_done as u64);
+        let eta = progress.eta().unwrap_or_default();
+        bar.set_message(format!(
+            "{:.2} Mrays/s, ETA {}s",
+            progress.rays_per_second() / 1.0e6,
+            eta.as_secs()
+        ));
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled() || self.error.lock().unwrap().is_some()
+    }
+}
+
+fn render_settings(options: &Options) -> RenderSettings {
+    RenderSettings {
+        width: options.width,
+        height: options.height,
+        samples_per_pixel: options.samples.unwrap_or(SAMPLES_PER_PIXEL),
+        adaptive: AdaptiveSettings {
+            min_samples: options.min_samples.unwrap_or(DEFAULT_MIN_SAMPLES),
+            noise_threshold: options.noise_threshold.unwrap_or(0.0),
+        },
+        filter: options.filter,
+        tile_size: options.tile_size,
+        tile_order: options.tile_order,
+        exposure: options.exposure,
+        white_balance: options.white_balance,
+        spectral: options.spectral,
+        ..RenderSettings::default()
+    }
+}
+
+// The options besides the render settings that shape the image, saved with
+// checkpoints so a render cannot be resumed into a different scene
+fn scene_description(options: &Options) -> String {
+    let lens = &options.lens;
+    let aperture_shape = match &lens.aperture_shape {
+        ApertureShape::Circle => "circle".to_string(),
+        ApertureShape::Polygon { blades, rotation } => {
+            format!("{} blades at {}", blades, rotation)
+        }
+        ApertureShape::Image(_) => format!(
+            "image {}",
+            options.aperture_image.as_deref().unwrap_or_default()
+        ),
+    };
+
+    format!(
+        "camera {:?} with fov {}, aperture {} {}, cat eye {}, anamorphic {}, tilt {}, swing {}, \
+         glass {:?}, thin film {:?}, layered {}, subsurface {}, alpha mask {}, \
+         lampshade {}, brushed {}, hair {}, diffuse {:?} with roughness {}",
+        options.projection,
+        options.vfov,
+        options.aperture,
+        aperture_shape,
+        lens.cat_eye,
+        lens.anamorphic,
+        lens.tilt,
+        lens.swing,
+        options.glass,
+        options.thin_film,
+        options.layered,
+        options.subsurface,
+        options.alpha_mask,
+        options.lampshade,
+        options.brushed,
+        options.hair,
+        options.diffuse,
+        options.roughness,
+    )
+}
+
+// Starts a fresh render, or picks up the one saved with --resume after
+// checking it was made with the same settings and scene
+fn initial_state(options: &Options, renderer: &Renderer) -> std::io::Result<RenderState> {
+    let Some(path) = &options.resume else {
+        let mut state = renderer.new_state(options.seed.unwrap_or_else(utils::random_seed));
+        state.scene = scene_description(options);
+        return Ok(state);
+    };
+
+    let mut state = RenderState::load(path)?;
+    state.check_resumable(&render_settings(options), &scene_description(options))?;
+
+    if let Some(samples) = options.samples {
+        state.samples_per_pixel = u32::max(samples, state.samples_done);
+    }
+    if let Some(min_samples) = options.min_samples {
+        state.adaptive.min_samples = min_samples;
+    }
+    if let Some(noise_threshold) = options.noise_threshold {
+        state.adaptive.noise_threshold = noise_threshold;
+    }
+
+    Ok(state)
+}
+
+fn render_to_window(options: &Options) -> std::io::Result<()> {
+    let mut buffer: Vec<u32> = vec![0; options.width * options.height];
     let mut window = Window::new(
         "Ray Tracer - Rendering...",
-        IMAGE_WIDTH as usize,
-        IMAGE_HEIGHT as usize,
+        options.width,
+        options.height,
         WindowOptions {
             resize: false,
             scale: minifb::Scale::X1,
@@ -139,126 +498,659 @@ fn render_to_window() {
         panic!("Unable to open window: {}", e);
     });
 
-    let world = random_scene();
-    let lookfrom = Point3::new(13.0, 2.0, 3.0);
-    let lookat = Point3::new(0.0, 0.0, 0.0);
-    let vup = Point3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
-
-    let camera = Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        20.0,
-        ASPECT_RATIO,
-        aperture,
-        dist_to_focus,
-    );
+    let renderer = Renderer::new(render_settings(options));
+    let mut state = initial_state(options, &renderer)?;
+
+    utils::seed_random(state.seed);
+    let scene = build_scene(options);
+    let camera = scene_camera(options).build();
+
+    let image = state.film.get_mut().unwrap().image();
+    for (pixel, color) in buffer.iter_mut().zip(image) {
+        *pixel = color::color_to_u32(color);
+    }
+
+    let cancel = CancellationToken::new();
+
+    let status = std::thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
 
-    for j in (0..IMAGE_HEIGHT).rev() {
-        let pixel_colors: Vec<_> = (0..IMAGE_WIDTH)
-            .into_par_iter()
-            .map(|i| {
-                let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-                for _ in 0..SAMPLES_PER_PIXEL {
-                    let u = (i as f64 + utils::random_double()) / (IMAGE_WIDTH - 1) as f64;
-                    let v = (j as f64 + utils::random_double()) / (IMAGE_HEIGHT - 1) as f64;
-                    let r = camera.get_ray(u, v);
-                    pixel_color += ray_color(&r, &world, MAX_DEPTH);
+        let state = &mut state;
+        let observer = CliObserver::new(
+            options,
+            cancel.clone(),
+            move |bounds: Tile, pixels: &[Color]| {
+                let pixels: Vec<_> = pixels.iter().map(|&c| color::color_to_u32(c)).collect();
+                sender.send((bounds, pixels)).ok();
+            },
+        );
+        let render = scope.spawn(move || {
+            let status = renderer.render_state(&scene, camera.as_ref(), state, &observer);
+            observer.finish().map(|_| status)
+        });
+
+        loop {
+            match receiver.recv_timeout(Duration::from_millis(16)) {
+                Ok((bounds, pixels)) => {
+                    let mut pixels = pixels.into_iter();
+                    for y in bounds.y0..bounds.y1 {
+                        for x in bounds.x0..bounds.x1 {
+                            buffer[y * options.width + x] = pixels.next().unwrap();
+                        }
+                    }
                 }
-                pixel_color
-            })
-            .collect();
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
 
-        for (i, color) in pixel_colors.into_iter().enumerate() {
-            let index = (IMAGE_HEIGHT - 1 - j) * IMAGE_WIDTH + i as i32;
-            buffer[index as usize] = color_to_u32(color, SAMPLES_PER_PIXEL);
+            window
+                .update_with_buffer(&buffer, options.width, options.height)
+                .unwrap();
+
+            if window.is_key_down(Key::Escape) {
+                cancel.cancel();
+            }
         }
 
-        window
-            .update_with_buffer(&buffer, IMAGE_WIDTH as usize, IMAGE_HEIGHT as usize)
-            .unwrap();
+        render.join().unwrap()
+    })?;
+
+    write_extra_outputs(
+        options,
+        state.aovs.get_mut().unwrap(),
+        state.stats.get_mut().unwrap(),
+    )?;
 
-        if window.is_key_down(Key::Escape) {
-            break;
+    if let (RenderStatus::Completed, Some(strength)) = (status, options.denoise) {
+        window.set_title("Ray Tracer - Denoising...");
+        let film = state.film.get_mut().unwrap();
+        let aovs = state.aovs.get_mut().unwrap();
+        let denoised = Denoiser::new(strength).denoise(&film.image(), aovs);
+        for (pixel, color) in buffer.iter_mut().zip(denoised) {
+            *pixel = color::color_to_u32(color);
         }
+        window
+            .update_with_buffer(&buffer, options.width, options.height)
+            .unwrap();
     }
 
     window.set_title("Rendering complete! (Press ESC to exit)");
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
         window.update();
-        std::thread::sleep(std::time::Duration::from_millis(16));
+        std::thread::sleep(Duration::from_millis(16));
     }
+
+    Ok(())
 }
 
-fn render_to_file() -> std::io::Result<()> {
-    let file = File::create("image.ppm")?;
-    let mut writer = BufWriter::new(file);
+// Writes the outputs besides the image that were asked for on the command
+// line: the AOV layers as DIR/image.<layer>.pfm and the sample heatmap
+fn write_extra_outputs(
+    options: &Options,
+    aovs: &AovBuffers,
+    samples: &SampleMap,
+) -> std::io::Result<()> {
+    if let Some(dir) = &options.aovs {
+        std::fs::create_dir_all(dir)?;
+        aovs.write(&format!("{}/image", dir))?;
+    }
 
-    writeln!(writer, "P3\n{} {}\n255", IMAGE_WIDTH, IMAGE_HEIGHT)?;
+    if let Some(path) = &options.heatmap {
+        samples.write_heatmap(path)?;
+    }
 
-    let bar = ProgressBar::new(IMAGE_HEIGHT as u64);
+    Ok(())
+}
+
+fn render_to_file(options: &Options) -> std::io::Result<()> {
+    let renderer = Renderer::new(render_settings(options));
+    let mut state = initial_state(options, &renderer)?;
+
+    utils::seed_random(state.seed);
+    let scene = build_scene(options);
+    let camera = scene_camera(options).build();
+
+    let bar = ProgressBar::new(0);
     bar.set_style(
         ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} ({percent}%)")
+            .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} ({percent}%) {msg}")
             .unwrap()
             .progress_chars("=> "),
     );
 
-    let world = random_scene();
-
-    let lookfrom = Point3::new(13.0, 2.0, 3.0);
-    let lookat = Point3::new(0.0, 0.0, 0.0);
-    let vup = Point3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
-
-    let camera = Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        20.0,
-        ASPECT_RATIO,
-        aperture,
-        dist_to_focus,
-    );
+    let observer = CliObserver::new(options, CancellationToken::new(), |_: Tile, _: &[Color]| {})
+        .with_progress_bar(bar.clone());
+    renderer.render_state(&scene, camera.as_ref(), &mut state, &observer);
+    observer.finish()?;
 
-    for j in (0..IMAGE_HEIGHT).rev() {
-        bar.inc(1);
+    let framebuffer = state.into_framebuffer();
 
-        let pixel_colors: Vec<_> = (0..IMAGE_WIDTH)
-            .into_par_iter()
-            .map(|i| {
-                let mut pixel_color = Color::new(0.0, 0.0, 0.0);
+    let mut image = framebuffer.pixels;
+    if let Some(strength) = options.denoise {
+        image = Denoiser::new(strength).denoise(&image, &framebuffer.aovs);
+    }
 
-                for _ in 0..SAMPLES_PER_PIXEL {
-                    let u = ((i as f64) + utils::random_double()) / (IMAGE_WIDTH - 1) as f64;
-                    let v = ((j as f64) + utils::random_double()) / (IMAGE_HEIGHT - 1) as f64;
-                    let r = camera.get_ray(u, v);
-                    pixel_color += ray_color(&r, &world, MAX_DEPTH);
+    write_ppm("image.ppm", framebuffer.width, framebuffer.height, &image)?;
+
+    write_extra_outputs(options, &framebuffer.aovs, &framebuffer.samples)?;
+
+    bar.finish_with_message("Image written!");
+
+    Ok(())
+}
+
+struct Options {
+    width: usize,
+    height: usize,
+    denoise: Option<f64>,
+    filter: Filter,
+    tile_size: usize,
+    tile_order: TileOrder,
+    samples: Option<u32>,
+    min_samples: Option<u32>,
+    noise_threshold: Option<f64>,
+    heatmap: Option<String>,
+    aovs: Option<String>,
+    seed: Option<u64>,
+    checkpoint: Option<String>,
+    checkpoint_interval: Duration,
+    resume: Option<String>,
+    projection: Projection,
+    vfov: f64,
+    aperture: f64,
+    lens: Lens,
+    aperture_image: Option<String>,
+    exposure: Option<Exposure>,
+    white_balance: Option<f64>,
+    spectral: bool,
+    glass: Option<Dispersion>,
+    thin_film: Option<f64>,
+    layered: bool,
+    subsurface: bool,
+    alpha_mask: bool,
+    lampshade: bool,
+    brushed: bool,
+    hair: bool,
+    diffuse: DiffuseModel,
+    roughness: f64,
+    stereo: Option<StereoLayout>,
+    interocular: f64,
+    convergence: Convergence,
+    convergence_distance: Option<f64>,
+    frames: Option<RangeInclusive<u32>>,
+    fps: f64,
+    output: String,
+}
+
+impl Options {
+    fn from_args() -> Options {
+        let mut options = Options {
+            width: IMAGE_WIDTH as usize,
+            height: IMAGE_HEIGHT as usize,
+            denoise: None,
+            filter: Filter::default(),
+            tile_size: DEFAULT_TILE_SIZE,
+            tile_order: TileOrder::Spiral,
+            samples: None,
+            min_samples: None,
+            noise_threshold: None,
+            heatmap: None,
+            aovs: None,
+            seed: None,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(DEFAULT_CHECKPOINT_INTERVAL),
+            resume: None,
+            projection: Projection::Perspective,
+            vfov: DEFAULT_VFOV,
+            aperture: DEFAULT_APERTURE,
+            lens: Lens::default(),
+            aperture_image: None,
+            exposure: None,
+            white_balance: None,
+            spectral: false,
+            glass: None,
+            thin_film: None,
+            layered: false,
+            subsurface: false,
+            alpha_mask: false,
+            lampshade: false,
+            brushed: false,
+            hair: false,
+            diffuse: DiffuseModel::Lambertian,
+            roughness: DEFAULT_ROUGHNESS,
+            stereo: None,
+            interocular: DEFAULT_INTEROCULAR,
+            convergence: Convergence::OffAxis,
+            convergence_distance: None,
+            frames: None,
+            fps: DEFAULT_FPS,
+            output: "images".to_string(),
+        };
+        let mut filter_radius = None;
+        let mut fov = None;
+        let mut blades = None;
+        let mut blade_rotation = 0.0;
+        let mut aperture_set = false;
+        let mut iso = None;
+        let mut shutter = None;
+        let mut f_number = None;
+        let mut args = std::env::args().skip(1).peekable();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--denoise" => {
+                    let strength = args
+                        .next_if(|value| !value.starts_with("--"))
+                        .map(|value| parse_arg(&arg, &value))
+                        .unwrap_or(DEFAULT_DENOISE_STRENGTH);
+                    options.denoise = Some(strength);
+                }
+                "--filter" => options.filter = parse_arg(&arg, &next_value(&arg, &mut args)),
+                "--filter-radius" => {
+                    let value = next_value(&arg, &mut args);
+                    let radius: f64 = parse_arg(&arg, &value);
+                    if !(radius.is_finite() && radius > 0.0) {
+                        usage(&format!("invalid value '{}' for {}", value, arg));
+                    }
+                    filter_radius = Some(radius);
+                }
+                "--tile-size" => {
+                    let value = next_value(&arg, &mut args);
+                    options.tile_size = parse_arg(&arg, &value);
+                    if options.tile_size == 0 {
+                        usage(&format!("invalid value '{}' for {}", value, arg));
+                    }
+                }
+                "--tile-order" => {
+                    options.tile_order = parse_arg(&arg, &next_value(&arg, &mut args));
+                }
+                "--samples" => {
+                    options.samples = Some(parse_arg(&arg, &next_value(&arg, &mut args)))
+                }
+                "--min-samples" => {
+                    options.min_samples = Some(parse_arg(&arg, &next_value(&arg, &mut args)));
+                }
+                "--adaptive" => {
+                    options.noise_threshold = Some(parse_arg(&arg, &next_value(&arg, &mut args)));
+                }
+                "--heatmap" => options.heatmap = Some(next_value(&arg, &mut args)),
+                "--aovs" => options.aovs = Some(next_value(&arg, &mut args)),
+                "--seed" => options.seed = Some(parse_arg(&arg, &next_value(&arg, &mut args))),
+                "--checkpoint" => options.checkpoint = Some(next_value(&arg, &mut args)),
+                "--checkpoint-interval" => {
+                    let seconds = parse_arg(&arg, &next_value(&arg, &mut args));
+                    options.checkpoint_interval = Duration::from_secs(seconds);
+                }
+                "--resume" => options.resume = Some(next_value(&arg, &mut args)),
+                "--camera" => options.projection = parse_arg(&arg, &next_value(&arg, &mut args)),
+                "--fov" => fov = Some(parse_arg(&arg, &next_value(&arg, &mut args))),
+                "--aperture" => {
+                    options.aperture = parse_arg(&arg, &next_value(&arg, &mut args));
+                    aperture_set = true;
+                }
+                "--aperture-blades" => blades = Some(parse_arg(&arg, &next_value(&arg, &mut args))),
+                "--aperture-rotation" => {
+                    blade_rotation = parse_arg(&arg, &next_value(&arg, &mut args));
+                }
+                "--aperture-image" => options.aperture_image = Some(next_value(&arg, &mut args)),
+                "--cat-eye" => options.lens.cat_eye = parse_arg(&arg, &next_value(&arg, &mut args)),
+                "--anamorphic" => {
+                    options.lens.anamorphic = parse_arg(&arg, &next_value(&arg, &mut args));
                 }
+                "--tilt" => options.lens.tilt = parse_arg(&arg, &next_value(&arg, &mut args)),
+                "--swing" => options.lens.swing = parse_arg(&arg, &next_value(&arg, &mut args)),
+                "--iso" => iso = Some(parse_arg(&arg, &next_value(&arg, &mut args))),
+                "--shutter" => {
+                    let value = next_value(&arg, &mut args);
+                    shutter = Some(match value.split_once('/') {
+                        Some((numerator, denominator)) => {
+                            parse_arg::<f64>(&arg, numerator) / parse_arg::<f64>(&arg, denominator)
+                        }
+                        None => parse_arg(&arg, &value),
+                    });
+                }
+                "--f-stop" => f_number = Some(parse_arg(&arg, &next_value(&arg, &mut args))),
+                "--white-balance" => {
+                    options.white_balance = Some(parse_arg(&arg, &next_value(&arg, &mut args)));
+                }
+                "--spectral" => options.spectral = true,
+                "--glass" => options.glass = Some(parse_arg(&arg, &next_value(&arg, &mut args))),
+                "--thin-film" => {
+                    options.thin_film = Some(parse_arg(&arg, &next_value(&arg, &mut args)));
+                }
+                "--layered" => options.layered = true,
+                "--subsurface" => options.subsurface = true,
+                "--alpha-mask" => options.alpha_mask = true,
+                "--lampshade" => options.lampshade = true,
+                "--brushed" => options.brushed = true,
+                "--hair" => options.hair = true,
+                "--diffuse" => options.diffuse = parse_arg(&arg, &next_value(&arg, &mut args)),
+                "--roughness" => options.roughness = parse_arg(&arg, &next_value(&arg, &mut args)),
+                "--stereo" => options.stereo = Some(parse_arg(&arg, &next_value(&arg, &mut args))),
+                "--ipd" => options.interocular = parse_arg(&arg, &next_value(&arg, &mut args)),
+                "--convergence" => {
+                    options.convergence = parse_arg(&arg, &next_value(&arg, &mut args));
+                }
+                "--convergence-distance" => {
+                    options.convergence_distance =
+                        Some(parse_arg(&arg, &next_value(&arg, &mut args)));
+                }
+                "--frames" => {
+                    let value = next_value(&arg, &mut args);
+                    let (first, last) = value.split_once('-').unwrap_or((&value, &value));
+                    options.frames = Some(parse_arg(&arg, first)..=parse_arg(&arg, last));
+                }
+                "--fps" => options.fps = parse_arg(&arg, &next_value(&arg, &mut args)),
+                "--output" => options.output = next_value(&arg, &mut args),
+                _ => usage(&format!("unknown option '{}'", arg)),
+            }
+        }
+
+        let checkpointing = options.checkpoint.is_some() || options.resume.is_some();
+        if checkpointing && options.frames.is_some() {
+            usage("--frames cannot be combined with --checkpoint or --resume");
+        }
+        if checkpointing && options.stereo.is_some() {
+            usage("--stereo cannot be combined with --checkpoint or --resume");
+        }
+        if options.frames.is_some() && options.stereo.is_some() {
+            usage("--frames cannot be combined with --stereo");
+        }
+
+        if iso.is_some() || shutter.is_some() || f_number.is_some() {
+            if aperture_set {
+                usage("--aperture cannot be combined with --iso, --shutter or --f-stop");
+            }
+
+            let default = Exposure::default();
+            options.exposure = Some(Exposure {
+                iso: iso.unwrap_or(default.iso),
+                shutter: shutter.unwrap_or(default.shutter),
+                f_number: f_number.unwrap_or(default.f_number),
+            });
+        }
+
+        if let Some(radius) = filter_radius {
+            options.filter = options.filter.with_radius(radius);
+        }
+
+        if let Some(path) = &options.aperture_image {
+            let image = ApertureImage::load(path)
+                .unwrap_or_else(|e| usage(&format!("cannot load aperture image {}: {}", path, e)));
+            options.lens.aperture_shape = ApertureShape::Image(Arc::new(image));
+        } else if let Some(blades) = blades {
+            options.lens.aperture_shape = ApertureShape::Polygon {
+                blades,
+                rotation: blade_rotation,
+            };
+        }
 
-                pixel_color
-            })
-            .collect();
+        if options.projection == Projection::Equirectangular {
+            options.height = PANORAMA_HEIGHT as usize;
+        }
 
-        for pixel_color in pixel_colors {
-            color::write_color(&mut writer, pixel_color, SAMPLES_PER_PIXEL);
+        // Perspective and orthographic cameras take it as the vertical field
+        // of view, fisheyes as the angle across the image circle
+        if let Some(fov) = fov {
+            match &mut options.projection {
+                Projection::Perspective | Projection::Orthographic => {
+                    if !(fov > 0.0 && fov < 180.0) {
+                        usage(&format!("invalid value '{}' for --fov", fov));
+                    }
+                    options.vfov = fov;
+                }
+                Projection::Fisheye { fov: angle, .. } => *angle = fov,
+                Projection::Equirectangular => {
+                    usage("--fov does not apply to the equirectangular camera")
+                }
+            }
         }
+
+        options
     }
 
-    bar.finish_with_message("Image written!");
+    // Resumed renders keep saving to the checkpoint they were loaded from
+    fn checkpoint_path(&self) -> Option<&str> {
+        self.checkpoint.as_deref().or(self.resume.as_deref())
+    }
+}
+
+fn next_value(name: &str, args: &mut impl Iterator<Item = String>) -> String {
+    args.next()
+        .unwrap_or_else(|| usage(&format!("missing value for {}", name)))
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| usage(&format!("invalid value '{}' for {}", value, name)))
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("error: {}", error);
+    eprintln!();
+    eprintln!("Usage: raytracer [OPTIONS]");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!(
+        "  --denoise [STRENGTH]  Denoise the image after rendering (default strength {})",
+        DEFAULT_DENOISE_STRENGTH
+    );
+    eprintln!("  --filter NAME         Pixel reconstruction filter: box, tent, gaussian, mitchell or lanczos");
+    eprintln!("  --filter-radius R     Filter radius in pixels");
+    eprintln!(
+        "  --tile-size N         Tile edge length in pixels (default {})",
+        DEFAULT_TILE_SIZE
+    );
+    eprintln!("  --tile-order ORDER    Tile order: spiral, hilbert or scanline (default spiral)");
+    eprintln!(
+        "  --samples N           Samples per pixel, the maximum with --adaptive (default {})",
+        SAMPLES_PER_PIXEL
+    );
+    eprintln!(
+        "  --min-samples N       Samples every pixel gets before adaptive sampling kicks in (default {})",
+        DEFAULT_MIN_SAMPLES
+    );
+    eprintln!(
+        "  --adaptive THRESHOLD  Stop sampling pixels once their noise falls below THRESHOLD"
+    );
+    eprintln!("  --heatmap FILE        Write a heatmap of the samples spent per pixel");
+    eprintln!(
+        "  --aovs DIR            Write albedo, normal, depth and other layers as PFM images to DIR"
+    );
+    eprintln!("  --seed N              Seed for the scene and camera samples");
+    eprintln!("  --checkpoint FILE     Periodically save the render state to FILE");
+    eprintln!(
+        "  --checkpoint-interval SECONDS  Minimum time between checkpoints (default {})",
+        DEFAULT_CHECKPOINT_INTERVAL
+    );
+    eprintln!(
+        "  --resume FILE         Continue the render saved in FILE, optionally with more --samples"
+    );
+    eprintln!("  --camera NAME         Camera: perspective, orthographic, fisheye, fisheye-equisolid or equirectangular");
+    eprintln!(
+        "  --fov DEGREES         Vertical field of view (default {}), or of the fisheye image circle (default 180)",
+        DEFAULT_VFOV
+    );
+    eprintln!(
+        "  --aperture DIAMETER   Lens aperture diameter, 0 for a pinhole (default {})",
+        DEFAULT_APERTURE
+    );
+    eprintln!("  --aperture-blades N   Polygonal aperture with N diaphragm blades");
+    eprintln!("  --aperture-rotation DEGREES  Rotation of the aperture blades");
+    eprintln!("  --aperture-image FILE Aperture shaped like a PPM image, bright where open");
+    eprintln!("  --cat-eye AMOUNT      Clip bokeh towards the image corners, from 0 to 1");
+    eprintln!("  --anamorphic RATIO    Anamorphic squeeze, stretching bokeh vertically");
+    eprintln!("  --tilt DEGREES        Tilt the focal plane, pushing its top away");
+    eprintln!("  --swing DEGREES       Swing the focal plane, pushing its right side away");
+    eprintln!("  --iso N               Film speed of a physically exposed camera (default 100)");
+    eprintln!(
+        "  --shutter SECONDS     Exposure time, like 1/125, which also sets the motion blur (default 1/125)"
+    );
+    eprintln!(
+        "  --f-stop N            F-number, setting both exposure and depth of field (default 16)"
+    );
+    eprintln!("  --white-balance KELVIN  Color temperature that comes out white");
+    eprintln!("  --spectral            Trace sampled wavelengths instead of RGB");
+    eprintln!("  --thin-film NM        Make the small glass spheres soap bubbles and coat the big metal one, NM thick");
+    eprintln!("  --layered             Car paint on the small diffuse spheres and varnished wood on the big one");
+    eprintln!("  --subsurface          Make the big diffuse sphere translucent wax");
+    eprintln!("  --alpha-mask          Cut a checker of mostly clear squares out of the big diffuse sphere");
+    eprintln!("  --lampshade           Make the big diffuse sphere a lit paper lampshade");
+    eprintln!("  --brushed             Make the big metal sphere anisotropic brushed metal");
+    eprintln!("  --hair                Grow brown fur on the big diffuse sphere");
+    eprintln!(
+        "  --diffuse MODEL       Diffuse material: lambertian, oren-nayar or eon (default lambertian)"
+    );
+    eprintln!(
+        "  --roughness R         Roughness of the oren-nayar and eon models (default {})",
+        DEFAULT_ROUGHNESS
+    );
+    eprintln!(
+        "  --glass NAME          Dispersive glass for the big sphere: bk7, fused-silica or diamond"
+    );
+    eprintln!("  --stereo LAYOUT       Render a stereo pair: separate, side-by-side or over-under");
+    eprintln!(
+        "  --ipd DISTANCE        Distance between the eyes in scene units (default {})",
+        DEFAULT_INTEROCULAR
+    );
+    eprintln!("  --convergence MODE    Stereo convergence: off-axis or toe-in (default off-axis)");
+    eprintln!("  --convergence-distance D  Distance that appears at screen depth (default the focus distance)");
+    eprintln!("  --frames FIRST-LAST   Render frames of the camera orbit animation");
+    eprintln!(
+        "  --fps N               Frames per second of the animation (default {})",
+        DEFAULT_FPS
+    );
+    eprintln!("  --output DIR          Directory for numbered frames (default images)");
+    std::process::exit(2);
+}
+
+fn write_ppm(path: &str, width: usize, height: usize, image: &[Color]) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    writeln!(writer, "P3\n{} {}\n255", width, height)?;
+
+    for pixel_color in image {
+        color::write_color(&mut writer, *pixel_color, 1);
+    }
+
+    writer.flush()
+}
+
+// Writes frames as OUTPUT/0001.ppm and so on
+fn render_sequence(options: &Options, frames: RangeInclusive<u32>) -> std::io::Result<()> {
+    let renderer = Renderer::new(render_settings(options));
+    let sequence = SequenceRenderer::new(renderer, frames, options.fps);
+
+    let seed = options.seed.unwrap_or_else(utils::random_seed);
+    utils::seed_random(seed);
+    let mut animation = scene_animation(options);
+
+    std::fs::create_dir_all(&options.output)?;
+
+    let bar = ProgressBar::new(0);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{prefix} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} ({percent}%) {msg}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    bar.set_prefix(format!("frame {}", sequence.frames().start()));
+
+    let observer = CliObserver::new(options, CancellationToken::new(), |_: Tile, _: &[Color]| {})
+        .with_progress_bar(bar.clone());
+
+    sequence.render(&mut animation, seed, &observer, |frame, framebuffer| {
+        let mut image = framebuffer.pixels;
+        if let Some(strength) = options.denoise {
+            image = Denoiser::new(strength).denoise(&image, &framebuffer.aovs);
+        }
+
+        let path = format!("{}/{:04}.ppm", options.output, frame);
+        write_ppm(&path, framebuffer.width, framebuffer.height, &image)?;
+        bar.set_prefix(format!("frame {}", frame + 1));
+        Ok(())
+    })?;
+    observer.finish()?;
+
+    bar.finish_with_message("Frames written!");
+
+    Ok(())
+}
+
+// Renders both eyes with the same seed, so their noise matches, and writes
+// image_left.ppm and image_right.ppm or a single packed image.ppm
+fn render_stereo(options: &Options, layout: StereoLayout) -> std::io::Result<()> {
+    let renderer = Renderer::new(render_settings(options));
+    let seed = options.seed.unwrap_or_else(utils::random_seed);
+
+    utils::seed_random(seed);
+    let scene = build_scene(options);
+    let description = scene_camera(options);
+    let rig = StereoRig {
+        interocular: options.interocular,
+        convergence_distance: options
+            .convergence_distance
+            .unwrap_or(description.focus_dist),
+        convergence: options.convergence,
+    };
+
+    let bar = ProgressBar::new(0);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{prefix} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} ({percent}%) {msg}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+
+    let mut views = Vec::new();
+    for (eye, name) in [(Eye::Left, "left"), (Eye::Right, "right")] {
+        bar.set_prefix(format!("{} eye", name));
+
+        let camera = rig.camera(&description, eye);
+        let observer =
+            CliObserver::new(options, CancellationToken::new(), |_: Tile, _: &[Color]| {})
+                .with_progress_bar(bar.clone());
+        let mut state = renderer.new_state(seed);
+        renderer.render_state(&scene, camera.as_ref(), &mut state, &observer);
+        observer.finish()?;
+
+        let framebuffer = state.into_framebuffer();
+        let mut image = framebuffer.pixels;
+        if let Some(strength) = options.denoise {
+            image = Denoiser::new(strength).denoise(&image, &framebuffer.aovs);
+        }
+        views.push((name, image));
+    }
+
+    let (width, height) = (options.width, options.height);
+    match stereo::pack(layout, &views[0].1, &views[1].1, width, height) {
+        Some((image, width, height)) => write_ppm("image.ppm", width, height, &image)?,
+        None => {
+            for (name, image) in &views {
+                write_ppm(&format!("image_{}.ppm", name), width, height, image)?;
+            }
+        }
+    }
+
+    bar.finish_with_message("Images written!");
 
     Ok(())
 }
 
 fn main() -> std::io::Result<()> {
-    if DISPLAY_IN_WINDOW {
-        render_to_window();
+    let options = Options::from_args();
+
+    if let Some(frames) = options.frames.clone() {
+        render_sequence(&options, frames)?;
+    } else if let Some(layout) = options.stereo {
+        render_stereo(&options, layout)?;
+    } else if DISPLAY_IN_WINDOW {
+        render_to_window(&options)?;
     } else {
-        render_to_file()?;
+        render_to_file(&options)?;
     }
 
     Ok(())
@@ -1,16 +1,66 @@
-use crate::color::Color;
-use crate::hittable::HitRecord;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::color::{self, Color};
+use crate::curve;
+use crate::hittable::{HitRecord, Hittable};
 use crate::ray::Ray;
-use crate::utils;
-use crate::vec3;
+use crate::spectrum::{self, SampledSpectrum, RGB_WAVELENGTHS, WAVELENGTH_SAMPLES};
+use crate::texture::{SolidColor, Texture};
+use crate::utils::{self, PI};
+use crate::vec3::{self, Vec3};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ScatterKind {
+    Diffuse,
+    Specular,
+    Transmission,
+}
 
 pub struct ScatterRecord {
     pub attenuation: Color,
+    // Attenuation at a spectral path's wavelengths, for materials whose
+    // color depends on wavelength in ways an RGB uplift cannot capture.
+    // Replaces `attenuation` when set.
+    pub spectral_attenuation: Option<SampledSpectrum>,
     pub scattered: Ray,
+    pub kind: ScatterKind,
 }
 
 pub trait Material: Send + Sync {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord>;
+
+    // BRDF times the cosine term, used for light sampling
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // Density per solid angle of `scatter` choosing the direction of
+    // `scattered`. Zero for materials that only scatter into discrete
+    // directions.
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    // The scattering volume inside the surface, for materials whose light
+    // wanders beneath it before coming back out
+    fn subsurface(&self) -> Option<&Subsurface> {
+        None
+    }
+
+    // How much of the light meeting the surface it stops, from 0 for a hole
+    // to 1. The rest passes straight through.
+    fn opacity(&self, _rec: &HitRecord) -> f64 {
+        1.0
+    }
 }
 
 pub struct Lambertian {
@@ -24,7 +74,7 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let mut scatter_direction = rec.normal + vec3::random_unit_vector();
 
         if scatter_direction.near_zero() {
@@ -33,9 +83,306 @@ impl Material for Lambertian {
 
         Some(ScatterRecord {
             attenuation: self.albedo,
-            scattered: Ray::new(rec.p, scatter_direction),
+            spectral_attenuation: None,
+            scattered: r_in.spawn(rec.p, scatter_direction),
+            kind: ScatterKind::Diffuse,
         })
     }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let cosine = vec3::dot(rec.normal, vec3::unit_vector(scattered.direction()));
+        if cosine <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.albedo * (cosine / PI)
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        cosine_pdf(rec, scattered)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+// Density of directions drawn around the normal in proportion to the cosine,
+// as diffuse materials draw them
+fn cosine_pdf(rec: &HitRecord, scattered: &Ray) -> f64 {
+    let cosine = vec3::dot(rec.normal, vec3::unit_vector(scattered.direction()));
+    f64::max(cosine, 0.0) / PI
+}
+
+// A direction drawn around the normal in proportion to the cosine
+fn cosine_direction(rec: &HitRecord) -> Vec3 {
+    let direction = rec.normal + vec3::random_unit_vector();
+    if direction.near_zero() {
+        rec.normal
+    } else {
+        direction
+    }
+}
+
+// Cosines of the incoming and outgoing directions to the normal, and the
+// Oren-Nayar term comparing their azimuths
+fn diffuse_angles(r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> (f64, f64, f64) {
+    let wi = -vec3::unit_vector(r_in.direction());
+    let wo = vec3::unit_vector(scattered.direction());
+    let cos_i = f64::clamp(vec3::dot(rec.normal, wi), 0.0, 1.0);
+    let cos_o = f64::clamp(vec3::dot(rec.normal, wo), 0.0, 1.0);
+
+    // cos(φi - φo) sin θi sin θo, over the larger of the cosines where it's
+    // positive
+    let s = vec3::dot(wi, wo) - cos_i * cos_o;
+    let s_over_t = if s > 0.0 {
+        s / f64::max(f64::max(cos_i, cos_o), 1e-7)
+    } else {
+        s
+    };
+    (cos_i, cos_o, s_over_t)
+}
+
+// Sharpness of the lobe drawing directions back towards the viewer
+const RETRO_EXPONENT: f64 = 1.0;
+
+// Rough diffuse surfaces scatter light back towards where it came from,
+// most of all at grazing angles, where drawing directions by the cosine
+// alone leaves fireflies. They draw from a lobe around the incoming
+// direction instead with these odds, growing with `roughness` from 0 to 1.
+fn retro_probability(roughness: f64, r_in: &Ray, rec: &HitRecord) -> f64 {
+    let cos_i = vec3::dot(rec.normal, -vec3::unit_vector(r_in.direction()));
+    0.5 * roughness * (1.0 - f64::clamp(cos_i, 0.0, 1.0))
+}
+
+// A direction drawn from the retro lobe with odds `q`, otherwise around the
+// normal in proportion to the cosine. Retro directions falling below the
+// surface are mirrored back above it.
+fn rough_diffuse_direction(q: f64, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+    if q > utils::random_double() {
+        let cos_theta = utils::random_double().powf(1.0 / (RETRO_EXPONENT + 1.0));
+        let direction = around(
+            -r_in.direction(),
+            cos_theta,
+            2.0 * PI * utils::random_double(),
+        );
+        let below = vec3::dot(direction, rec.normal);
+        if below < 0.0 {
+            direction - 2.0 * below * rec.normal
+        } else {
+            direction
+        }
+    } else {
+        cosine_direction(rec)
+    }
+}
+
+fn rough_diffuse_pdf(q: f64, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+    let wi = -vec3::unit_vector(r_in.direction());
+    let wo = vec3::unit_vector(scattered.direction());
+    let cosine = vec3::dot(wo, rec.normal);
+    if cosine <= 0.0 {
+        return 0.0;
+    }
+
+    // Either the direction itself or its mirror image may have been drawn
+    let lobe = |w: Vec3| {
+        (RETRO_EXPONENT + 1.0) / (2.0 * PI) * f64::max(vec3::dot(wi, w), 0.0).powf(RETRO_EXPONENT)
+    };
+    let retro = lobe(wo) + lobe(wo - 2.0 * cosine * rec.normal);
+    (1.0 - q) * cosine / PI + q * retro
+}
+
+// Scatters off a rough diffuse surface whose BRDF times π is `brdf`
+fn rough_diffuse_scatter(
+    q: f64,
+    r_in: &Ray,
+    rec: &HitRecord,
+    brdf: impl Fn(&Ray) -> Color,
+) -> Option<ScatterRecord> {
+    let scattered = r_in.spawn(rec.p, rough_diffuse_direction(q, r_in, rec));
+    let cosine = cosine_pdf(rec, &scattered);
+    if cosine <= 0.0 {
+        return None;
+    }
+
+    Some(ScatterRecord {
+        attenuation: brdf(&scattered) * (cosine / rough_diffuse_pdf(q, r_in, rec, &scattered)),
+        spectral_attenuation: None,
+        scattered,
+        kind: ScatterKind::Diffuse,
+    })
+}
+
+// A rough diffuse surface made of V-shaped Lambertian facets, after Oren and
+// Nayar's qualitative model. Rough surfaces look flatter than Lambertian
+// ones and brighten towards the light, like clay, concrete and the moon.
+pub struct OrenNayar {
+    albedo: Color,
+    roughness: f64,
+    a: f64,
+    b: f64,
+}
+
+impl OrenNayar {
+    // `roughness` is the standard deviation of the facet angles in radians,
+    // 0 giving a Lambertian surface
+    pub fn new(albedo: Color, roughness: f64) -> OrenNayar {
+        let sigma2 = roughness * roughness;
+        OrenNayar {
+            albedo,
+            roughness,
+            a: 1.0 - 0.5 * sigma2 / (sigma2 + 0.33),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+
+    // BRDF times π
+    fn brdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let (_, _, s_over_t) = diffuse_angles(r_in, rec, scattered);
+        self.albedo * (self.a + self.b * f64::max(s_over_t, 0.0))
+    }
+
+    fn retro_probability(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        retro_probability(f64::min(self.roughness, 1.0), r_in, rec)
+    }
+}
+
+impl Material for OrenNayar {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let q = self.retro_probability(r_in, rec);
+        rough_diffuse_scatter(q, r_in, rec, |scattered| self.brdf(r_in, rec, scattered))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        // The cosine over π, times the BRDF times π
+        let cosine = cosine_pdf(rec, scattered);
+        if cosine <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.brdf(r_in, rec, scattered) * cosine
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        rough_diffuse_pdf(self.retro_probability(r_in, rec), r_in, rec, scattered)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+const FON_CONSTANT_1: f64 = 0.5 - 2.0 / (3.0 * PI);
+const FON_CONSTANT_2: f64 = 2.0 / 3.0 - 28.0 / (15.0 * PI);
+
+// Energy-preserving Oren-Nayar (EON), after Portsmouth et al. Fujii's
+// single-scattering form of Oren-Nayar loses the light that bounces between
+// facets, darkening rough surfaces. A multiple-scattering lobe adds it back,
+// so a white surface reflects all light at any roughness.
+pub struct RoughDiffuse {
+    albedo: Color,
+    roughness: f64,
+}
+
+impl RoughDiffuse {
+    // `roughness` runs from 0, Lambertian, to 1
+    pub fn new(albedo: Color, roughness: f64) -> RoughDiffuse {
+        RoughDiffuse {
+            albedo,
+            roughness: roughness.clamp(0.0, 1.0),
+        }
+    }
+
+    // Fraction of light arriving at `cosine` that the single-scattering lobe
+    // reflects
+    fn single_scattering_albedo(&self, cosine: f64) -> f64 {
+        let r = self.roughness;
+        let a = 1.0 / (1.0 + FON_CONSTANT_1 * r);
+        let mu = f64::clamp(cosine, 1e-4, 1.0);
+        let sin = f64::sqrt(1.0 - mu * mu);
+        let g = sin * (f64::acos(mu) - sin * mu)
+            + (2.0 / 3.0) * ((sin / mu) * (1.0 - sin * sin * sin) - sin);
+        a + r * a * g / PI
+    }
+
+    // BRDF times π
+    fn brdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let r = self.roughness;
+        let (cos_i, cos_o, s_over_t) = diffuse_angles(r_in, rec, scattered);
+
+        let a = 1.0 / (1.0 + FON_CONSTANT_1 * r);
+        let single = self.albedo * (a * (1.0 + r * s_over_t));
+
+        let eps = 1.0e-7;
+        let average = a * (1.0 + FON_CONSTANT_2 * r);
+        let ms_albedo = |rho: f64| rho * rho * average / (1.0 - rho * (1.0 - average));
+        let multiple = f64::max(eps, 1.0 - self.single_scattering_albedo(cos_o))
+            * f64::max(eps, 1.0 - self.single_scattering_albedo(cos_i))
+            / f64::max(eps, 1.0 - average);
+
+        single
+            + multiple
+                * Color::new(
+                    ms_albedo(self.albedo.x()),
+                    ms_albedo(self.albedo.y()),
+                    ms_albedo(self.albedo.z()),
+                )
+    }
+}
+
+impl Material for RoughDiffuse {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let q = retro_probability(self.roughness, r_in, rec);
+        rough_diffuse_scatter(q, r_in, rec, |scattered| self.brdf(r_in, rec, scattered))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        // The cosine over π, times the BRDF times π
+        let cosine = cosine_pdf(rec, scattered);
+        if cosine <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.brdf(r_in, rec, scattered) * cosine
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let q = retro_probability(self.roughness, r_in, rec);
+        rough_diffuse_pdf(q, r_in, rec, scattered)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+// Which diffuse material to use for rough surfaces
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiffuseModel {
+    Lambertian,
+    OrenNayar,
+    EnergyPreserving,
+}
+
+impl DiffuseModel {
+    pub fn material(&self, albedo: Color, roughness: f64) -> Arc<dyn Material> {
+        match self {
+            DiffuseModel::Lambertian => Arc::new(Lambertian::new(albedo)),
+            DiffuseModel::OrenNayar => Arc::new(OrenNayar::new(albedo, roughness)),
+            DiffuseModel::EnergyPreserving => Arc::new(RoughDiffuse::new(albedo, roughness)),
+        }
+    }
+}
+
+impl FromStr for DiffuseModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DiffuseModel, String> {
+        match s {
+            "lambertian" => Ok(DiffuseModel::Lambertian),
+            "oren-nayar" => Ok(DiffuseModel::OrenNayar),
+            "eon" => Ok(DiffuseModel::EnergyPreserving),
+            _ => Err(format!("unknown diffuse model '{}'", s)),
+        }
+    }
 }
 
 pub struct Metal {
@@ -55,17 +402,197 @@ impl Metal {
 impl Material for Metal {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let reflected = vec3::reflect(vec3::unit_vector(r_in.direction()), rec.normal);
-        let scattered = Ray::new(rec.p, reflected + self.fuzz * vec3::random_in_unit_sphere());
+        let scattered = r_in.spawn(rec.p, reflected + self.fuzz * vec3::random_in_unit_sphere());
 
         if vec3::dot(scattered.direction(), rec.normal) > 0.0 {
             Some(ScatterRecord {
                 attenuation: self.albedo,
+                spectral_attenuation: None,
                 scattered,
+                kind: ScatterKind::Specular,
             })
         } else {
             None
         }
     }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+// A metal with GGX microfacets stretched along the surface, rougher across
+// one direction than the other, like brushed aluminium. `alpha_x` is the
+// roughness along the hit's tangent and `alpha_y` across it. A flow map can
+// turn the tangent, its red and green channels holding the new direction in
+// the surface's tangent frame, mapped from [-1, 1] to [0, 1].
+pub struct AnisotropicMetal {
+    albedo: Color,
+    alpha_x: f64,
+    alpha_y: f64,
+    flow_map: Option<Arc<dyn Texture>>,
+}
+
+impl AnisotropicMetal {
+    pub fn new(albedo: Color, alpha_x: f64, alpha_y: f64) -> AnisotropicMetal {
+        AnisotropicMetal {
+            albedo,
+            alpha_x: alpha_x.clamp(1e-3, 1.0),
+            alpha_y: alpha_y.clamp(1e-3, 1.0),
+            flow_map: None,
+        }
+    }
+
+    pub fn with_flow_map(mut self, texture: Arc<dyn Texture>) -> AnisotropicMetal {
+        self.flow_map = Some(texture);
+        self
+    }
+
+    // Tangent, bitangent and normal at the hit, the tangent turned by the
+    // flow map and made perpendicular to the normal
+    fn frame(&self, rec: &HitRecord) -> [Vec3; 3] {
+        let n = rec.normal;
+        let mut t = rec.tangent - vec3::dot(rec.tangent, n) * n;
+        if t.near_zero() {
+            t = around(n, 0.0, 0.0);
+        }
+        let t = vec3::unit_vector(t);
+        let b = vec3::cross(n, t);
+
+        let t = match &self.flow_map {
+            Some(map) => {
+                let flow = map.value(rec.u, rec.v, rec.p);
+                let turned = (2.0 * flow.x() - 1.0) * t + (2.0 * flow.y() - 1.0) * b;
+                if turned.near_zero() {
+                    t
+                } else {
+                    vec3::unit_vector(turned)
+                }
+            }
+            None => t,
+        };
+        [t, vec3::cross(n, t), n]
+    }
+
+    fn to_local(frame: &[Vec3; 3], v: Vec3) -> Vec3 {
+        let v = vec3::unit_vector(v);
+        Vec3::new(
+            vec3::dot(v, frame[0]),
+            vec3::dot(v, frame[1]),
+            vec3::dot(v, frame[2]),
+        )
+    }
+
+    // GGX distribution of microfacet normals
+    fn d(&self, h: Vec3) -> f64 {
+        let e = (h.x() / self.alpha_x).powi(2) + (h.y() / self.alpha_y).powi(2) + h.z() * h.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    // Smith's shadowing auxiliary function
+    fn lambda(&self, w: Vec3) -> f64 {
+        let tan2 =
+            ((self.alpha_x * w.x()).powi(2) + (self.alpha_y * w.y()).powi(2)) / (w.z() * w.z());
+        0.5 * (f64::sqrt(1.0 + tan2) - 1.0)
+    }
+
+    // Schlick's Fresnel with the albedo as the reflectance at normal incidence
+    fn fresnel(&self, cosine: f64) -> Color {
+        let white = Color::new(1.0, 1.0, 1.0);
+        self.albedo + f64::powf(1.0 - cosine, 5.0) * (white - self.albedo)
+    }
+
+    // A microfacet normal drawn from those visible from `wi`, after Heitz's
+    // "Sampling the GGX Distribution of Visible Normals"
+    fn sample_visible_normal(&self, wi: Vec3) -> Vec3 {
+        let vh = vec3::unit_vector(Vec3::new(
+            self.alpha_x * wi.x(),
+            self.alpha_y * wi.y(),
+            wi.z(),
+        ));
+        let len2 = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if len2 > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / len2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vec3::cross(vh, t1);
+
+        let r = utils::random_double().sqrt();
+        let phi = 2.0 * PI * utils::random_double();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * f64::sqrt(1.0 - p1 * p1) + s * r * phi.sin();
+
+        let nh = p1 * t1 + p2 * t2 + f64::sqrt(f64::max(0.0, 1.0 - p1 * p1 - p2 * p2)) * vh;
+        vec3::unit_vector(Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            f64::max(0.0, nh.z()),
+        ))
+    }
+}
+
+impl Material for AnisotropicMetal {
+    // Sampling visible normals leaves Fresnel times the share of reflected
+    // light that escapes the microfacets as the weight
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let frame = self.frame(rec);
+        let wi = AnisotropicMetal::to_local(&frame, -r_in.direction());
+        if wi.z() <= 0.0 {
+            return None;
+        }
+
+        let h = self.sample_visible_normal(wi);
+        let wo = vec3::reflect(-wi, h);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let lambda_i = self.lambda(wi);
+        let weight = (1.0 + lambda_i) / (1.0 + lambda_i + self.lambda(wo));
+        let direction = wo.x() * frame[0] + wo.y() * frame[1] + wo.z() * frame[2];
+
+        Some(ScatterRecord {
+            attenuation: weight * self.fresnel(vec3::dot(wi, h)),
+            spectral_attenuation: None,
+            scattered: r_in.spawn(rec.p, direction),
+            kind: ScatterKind::Specular,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let frame = self.frame(rec);
+        let wi = AnisotropicMetal::to_local(&frame, -r_in.direction());
+        let wo = AnisotropicMetal::to_local(&frame, scattered.direction());
+        if wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let h = vec3::unit_vector(wi + wo);
+        let g = 1.0 / (1.0 + self.lambda(wi) + self.lambda(wo));
+        self.fresnel(vec3::dot(wi, h)) * (self.d(h) * g / (4.0 * wi.z()))
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let frame = self.frame(rec);
+        let wi = AnisotropicMetal::to_local(&frame, -r_in.direction());
+        let wo = AnisotropicMetal::to_local(&frame, scattered.direction());
+        if wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return 0.0;
+        }
+
+        let h = vec3::unit_vector(wi + wo);
+        self.d(h) / (4.0 * wi.z() * (1.0 + self.lambda(wi)))
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
 pub struct Dielectric {
@@ -89,28 +616,1489 @@ impl Dielectric {
 
 impl Material for Dielectric {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
-        let refraction_ratio = if rec.front_face {
-            1.0 / self.ir
-        } else {
-            self.ir
+        let (direction, kind) = dielectric_direction(r_in, rec, self.ir);
+
+        Some(ScatterRecord {
+            attenuation: Color::new(1.0, 1.0, 1.0),
+            spectral_attenuation: None,
+            scattered: r_in.spawn(rec.p, direction),
+            kind,
+        })
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+// Reflects or refracts off a dielectric boundary, choosing between the two by
+// the Fresnel reflectance
+fn dielectric_direction(r_in: &Ray, rec: &HitRecord, ir: f64) -> (Vec3, ScatterKind) {
+    let refraction_ratio = if rec.front_face { 1.0 / ir } else { ir };
+
+    let unit_direction = vec3::unit_vector(r_in.direction());
+    let cos_theta = f64::min(vec3::dot(-unit_direction, rec.normal), 1.0);
+    let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
+
+    let cannot_refract = refraction_ratio * sin_theta > 1.0;
+    if cannot_refract
+        || Dielectric::reflectance(cos_theta, refraction_ratio) > utils::random_double()
+    {
+        (
+            vec3::reflect(unit_direction, rec.normal),
+            ScatterKind::Specular,
+        )
+    } else {
+        (
+            vec3::refract(unit_direction, rec.normal, refraction_ratio),
+            ScatterKind::Transmission,
+        )
+    }
+}
+
+// Index of refraction as a function of wavelength
+#[derive(Clone, Copy, Debug)]
+pub enum Dispersion {
+    // n = a + b / λ², with λ in micrometers
+    Cauchy { a: f64, b: f64 },
+    // n² = 1 + Σ bᵢ λ² / (λ² - cᵢ), with λ in micrometers
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    // Schott N-BK7 crown glass
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+
+    // Malitson's fit for fused silica
+    pub const FUSED_SILICA: Dispersion = Dispersion::Sellmeier {
+        b: [0.6961663, 0.4079426, 0.8974794],
+        c: [0.00467914826, 0.0135120631, 97.9340025],
+    };
+
+    // Peter's fit for diamond, which disperses light about three times as
+    // strongly as crown glass
+    pub const DIAMOND: Dispersion = Dispersion::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030625, 0.011236, 0.0],
+    };
+
+    // `lambda` is in nanometers
+    pub fn ior(&self, lambda: f64) -> f64 {
+        let l = lambda / 1000.0;
+        let l2 = l * l;
+        match *self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                f64::sqrt(1.0 + sum)
+            }
+        }
+    }
+}
+
+impl FromStr for Dispersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Dispersion, String> {
+        match s {
+            "bk7" => Ok(Dispersion::BK7),
+            "fused-silica" => Ok(Dispersion::FUSED_SILICA),
+            "diamond" => Ok(Dispersion::DIAMOND),
+            _ => Err(format!("unknown glass '{}'", s)),
+        }
+    }
+}
+
+// Glass whose index of refraction varies with wavelength, fanning white
+// light out into a spectrum. Spectral paths follow their hero wavelength
+// through it and drop the others. RGB paths pick one color channel to follow
+// on their first dispersive hit and keep it from then on.
+pub struct DispersiveDielectric {
+    dispersion: Dispersion,
+}
+
+impl DispersiveDielectric {
+    pub fn new(dispersion: Dispersion) -> DispersiveDielectric {
+        DispersiveDielectric { dispersion }
+    }
+}
+
+impl Material for DispersiveDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        if let Some(wavelengths) = r_in.wavelengths() {
+            let ir = self.dispersion.ior(wavelengths.hero());
+            let (direction, kind) = dielectric_direction(r_in, rec, ir);
+
+            let mut wavelengths = *wavelengths;
+            wavelengths.terminate_secondary();
+            return Some(ScatterRecord {
+                attenuation: Color::new(1.0, 1.0, 1.0),
+                spectral_attenuation: None,
+                scattered: r_in.spawn(rec.p, direction).with_wavelengths(wavelengths),
+                kind,
+            });
+        }
+
+        // Splitting weights the chosen channel by the odds of picking it
+        let (channel, weight) = match r_in.channel() {
+            Some(channel) => (channel, 1.0),
+            None => (usize::min((3.0 * utils::random_double()) as usize, 2), 3.0),
+        };
+        let ir = self.dispersion.ior(RGB_WAVELENGTHS[channel]);
+        let (direction, kind) = dielectric_direction(r_in, rec, ir);
+
+        let mut attenuation = [0.0; 3];
+        attenuation[channel] = weight;
+        Some(ScatterRecord {
+            attenuation: Color::new(attenuation[0], attenuation[1], attenuation[2]),
+            spectral_attenuation: None,
+            scattered: r_in.spawn(rec.p, direction).with_channel(channel),
+            kind,
+        })
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+// What lies beneath a thin film
+#[derive(Clone, Copy)]
+pub enum FilmBase {
+    // Nothing: a free-standing film like a soap bubble, with the same medium
+    // on both sides, so transmitted light carries straight on
+    Empty,
+    // Clear glass or similar with the given index of refraction
+    Dielectric { ir: f64 },
+    // A metal reflecting `albedo` at normal incidence. Its Fresnel term is
+    // approximated as an ideal conductor's phase flip.
+    Metal { albedo: Color, fuzz: f64 },
+}
+
+// A film a few hundred nanometers thick over a base. Light reflected off its
+// top and bottom interferes, tinting reflections with colors that shift with
+// the viewing angle and the film's thickness.
+pub struct ThinFilm {
+    // Nanometers
+    thickness: f64,
+    thickness_map: Option<Arc<dyn Texture>>,
+    ir: f64,
+    base: FilmBase,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, index_of_refraction: f64, base: FilmBase) -> ThinFilm {
+        ThinFilm {
+            thickness,
+            thickness_map: None,
+            ir: index_of_refraction,
+            base,
+        }
+    }
+
+    // A soap bubble shell
+    pub fn bubble(thickness: f64) -> ThinFilm {
+        ThinFilm::new(thickness, 1.33, FilmBase::Empty)
+    }
+
+    // Scales the thickness by the luminance of `texture` across the surface
+    pub fn with_thickness_map(mut self, texture: Arc<dyn Texture>) -> ThinFilm {
+        self.thickness_map = Some(texture);
+        self
+    }
+
+    // Reflectance at `lambda` nanometers for light arriving at `cos_theta`
+    // from a medium of index `n1`, with the base on the far side having index
+    // `n3`. `base_reflectance` replaces the bottom interface for metals.
+    fn reflectance(
+        &self,
+        thickness: f64,
+        lambda: f64,
+        cos_theta: f64,
+        n1: f64,
+        n3: f64,
+        base_reflectance: Option<f64>,
+    ) -> f64 {
+        let n2 = self.ir;
+        let sin2_1 = 1.0 - cos_theta * cos_theta;
+
+        let sin2_2 = (n1 / n2).powi(2) * sin2_1;
+        let sin2_3 = (n1 / n3).powi(2) * sin2_1;
+        if sin2_2 >= 1.0 || (base_reflectance.is_none() && sin2_3 >= 1.0) {
+            // Total internal reflection leaves nowhere else to go
+            return 1.0;
+        }
+        let cos2 = f64::sqrt(1.0 - sin2_2);
+        let cos3 = f64::sqrt(1.0 - sin2_3);
+
+        // Phase difference between successive reflections inside the film
+        let delta = 4.0 * PI * n2 * thickness * cos2 / lambda;
+
+        // Airy summation of the multiple reflections, for real amplitudes
+        let airy = |r12: f64, r23: f64| {
+            let cross = 2.0 * r12 * r23 * delta.cos();
+            (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+        };
+
+        let rs12 = (n1 * cos_theta - n2 * cos2) / (n1 * cos_theta + n2 * cos2);
+        let rp12 = (n2 * cos_theta - n1 * cos2) / (n2 * cos_theta + n1 * cos2);
+        let (rs23, rp23) = match base_reflectance {
+            Some(r) => (-r.sqrt(), -r.sqrt()),
+            None => (
+                (n2 * cos2 - n3 * cos3) / (n2 * cos2 + n3 * cos3),
+                (n3 * cos2 - n2 * cos3) / (n3 * cos2 + n2 * cos3),
+            ),
         };
 
+        0.5 * (airy(rs12, rs23) + airy(rp12, rp23))
+    }
+}
+
+impl Material for ThinFilm {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let unit_direction = vec3::unit_vector(r_in.direction());
         let cos_theta = f64::min(vec3::dot(-unit_direction, rec.normal), 1.0);
-        let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
 
-        let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction = if cannot_refract
-            || Self::reflectance(cos_theta, refraction_ratio) > utils::random_double()
-        {
-            vec3::reflect(unit_direction, rec.normal)
-        } else {
-            vec3::refract(unit_direction, rec.normal, refraction_ratio)
+        let thickness = match &self.thickness_map {
+            Some(map) => self.thickness * color::luminance(map.value(rec.u, rec.v, rec.p)),
+            None => self.thickness,
+        };
+
+        // The film faces outwards, so light from inside a dielectric meets
+        // the base first. Reflectance is the same from either side.
+        let (n1, n3, ratio) = match self.base {
+            FilmBase::Empty => (1.0, 1.0, 1.0),
+            FilmBase::Dielectric { ir } if rec.front_face => (1.0, ir, 1.0 / ir),
+            FilmBase::Dielectric { ir } => (ir, 1.0, ir),
+            FilmBase::Metal { .. } => (1.0, 1.0, 1.0),
+        };
+
+        // Reflectance per wavelength, at the path's wavelengths or at stand-ins
+        // for the color channels
+        let wavelengths = r_in.wavelengths();
+        let (lambdas, count) = match wavelengths {
+            Some(wavelengths) => (*wavelengths.lambda(), WAVELENGTH_SAMPLES),
+            None => (std::array::from_fn(|i| RGB_WAVELENGTHS[i.min(2)]), 3),
+        };
+        let base_reflectance = match self.base {
+            FilmBase::Metal { albedo, .. } => Some(match wavelengths {
+                Some(wavelengths) => SampledSpectrum::from_rgb(albedo, wavelengths),
+                None => SampledSpectrum::new(std::array::from_fn(|i| albedo[i.min(2)])),
+            }),
+            _ => None,
+        };
+        let reflectance: [f64; WAVELENGTH_SAMPLES] = std::array::from_fn(|i| {
+            let base = base_reflectance.map(|b| b[i]);
+            self.reflectance(thickness, lambdas[i], cos_theta, n1, n3, base)
+        });
+
+        let (direction, weights, kind) = match self.base {
+            FilmBase::Metal { fuzz, .. } => {
+                let reflected = vec3::reflect(unit_direction, rec.normal);
+                let direction = reflected + fuzz * vec3::random_in_unit_sphere();
+                if vec3::dot(direction, rec.normal) <= 0.0 {
+                    return None;
+                }
+                (direction, reflectance, ScatterKind::Specular)
+            }
+            _ => {
+                // Reflect or transmit in proportion to the average reflectance,
+                // weighting each wavelength by how its odds differ
+                let p = reflectance[..count].iter().sum::<f64>() / count as f64;
+                if p > utils::random_double() {
+                    let direction = vec3::reflect(unit_direction, rec.normal);
+                    (direction, reflectance.map(|r| r / p), ScatterKind::Specular)
+                } else {
+                    let direction = if ratio == 1.0 {
+                        unit_direction
+                    } else {
+                        vec3::refract(unit_direction, rec.normal, ratio)
+                    };
+                    let weights = reflectance.map(|r| (1.0 - r) / (1.0 - p));
+                    (direction, weights, ScatterKind::Transmission)
+                }
+            }
+        };
+
+        let (attenuation, spectral_attenuation) = match wavelengths {
+            Some(_) => (
+                Color::new(1.0, 1.0, 1.0),
+                Some(SampledSpectrum::new(weights)),
+            ),
+            None => (Color::new(weights[0], weights[1], weights[2]), None),
         };
 
         Some(ScatterRecord {
-            attenuation: Color::new(1.0, 1.0, 1.0),
-            scattered: Ray::new(rec.p, direction),
+            attenuation,
+            spectral_attenuation,
+            scattered: r_in.spawn(rec.p, direction),
+            kind,
         })
     }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        match self.base {
+            FilmBase::Metal { albedo, .. } => albedo,
+            _ => Color::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+// Two materials blended by a weight, which may vary across the surface. Each
+// scatter picks one of them with the weight's odds.
+pub struct Mix {
+    first: Arc<dyn Material>,
+    second: Arc<dyn Material>,
+    // Luminance gives the share of `second`
+    weight: Arc<dyn Texture>,
+}
+
+impl Mix {
+    // `weight` is the share of `second`, 0 leaving only `first`
+    pub fn new(first: Arc<dyn Material>, second: Arc<dyn Material>, weight: f64) -> Mix {
+        let weight = weight.clamp(0.0, 1.0);
+        Mix::textured(
+            first,
+            second,
+            Arc::new(SolidColor::new(Color::new(weight, weight, weight))),
+        )
+    }
+
+    pub fn textured(
+        first: Arc<dyn Material>,
+        second: Arc<dyn Material>,
+        weight: Arc<dyn Texture>,
+    ) -> Mix {
+        Mix {
+            first,
+            second,
+            weight,
+        }
+    }
+
+    fn weight(&self, rec: &HitRecord) -> f64 {
+        color::luminance(self.weight.value(rec.u, rec.v, rec.p)).clamp(0.0, 1.0)
+    }
+}
+
+impl Material for Mix {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        if self.weight(rec) > utils::random_double() {
+            self.second.scatter(r_in, rec)
+        } else {
+            self.first.scatter(r_in, rec)
+        }
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let w = self.weight(rec);
+        (1.0 - w) * self.first.eval(r_in, rec, scattered)
+            + w * self.second.eval(r_in, rec, scattered)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let w = self.weight(rec);
+        (1.0 - w) * self.first.pdf(r_in, rec, scattered) + w * self.second.pdf(r_in, rec, scattered)
+    }
+
+    // Light sampling only sees the non-specular part, through `eval`
+    fn is_specular(&self) -> bool {
+        self.first.is_specular() && self.second.is_specular()
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        let w = self.weight(rec);
+        (1.0 - w) * self.first.albedo(rec) + w * self.second.albedo(rec)
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> f64 {
+        let w = self.weight(rec);
+        (1.0 - w) * self.first.opacity(rec) + w * self.second.opacity(rec)
+    }
+}
+
+// Times light may bounce between a coat and its base before giving up
+const MAX_COAT_BOUNCES: usize = 8;
+
+// A clear dielectric layer over another material, like lacquer or varnish.
+// Light either reflects off the coat or refracts through it to the base,
+// and whatever the base sends back up may reflect inside the coat again
+// before escaping. A tinted interlayer absorbs light on each crossing.
+pub struct ClearCoat {
+    base: Arc<dyn Material>,
+    ir: f64,
+    // Absorption per unit of thickness, per channel
+    absorption: Color,
+    thickness: f64,
+}
+
+impl ClearCoat {
+    pub fn new(base: Arc<dyn Material>, index_of_refraction: f64) -> ClearCoat {
+        ClearCoat {
+            base,
+            ir: index_of_refraction,
+            absorption: Color::new(0.0, 0.0, 0.0),
+            thickness: 0.0,
+        }
+    }
+
+    // Tints the coat, so that light crossing it straight down keeps
+    // exp(-absorption * thickness) of its energy
+    pub fn with_interlayer(mut self, absorption: Color, thickness: f64) -> ClearCoat {
+        self.absorption = absorption;
+        self.thickness = thickness;
+        self
+    }
+
+    // Amber varnish, as over wood
+    pub fn varnish(base: Arc<dyn Material>) -> ClearCoat {
+        ClearCoat::new(base, 1.5).with_interlayer(Color::new(0.05, 0.2, 0.6), 1.0)
+    }
+
+    // Paint of the given color with a share of `flakes` metallic flakes,
+    // under a clearcoat
+    pub fn car_paint(color: Color, flakes: f64) -> ClearCoat {
+        let flake_color = 0.5 * (color + Color::new(1.0, 1.0, 1.0));
+        let base = Mix::new(
+            Arc::new(Lambertian::new(color)),
+            Arc::new(Metal::new(flake_color, 0.3)),
+            flakes,
+        );
+        ClearCoat::new(Arc::new(base), 1.5)
+    }
+
+    // Fraction of light left after crossing the interlayer at `cosine` to the
+    // normal
+    fn transmittance(&self, cosine: f64) -> Color {
+        let depth = self.thickness / f64::max(cosine, 1e-4);
+        Color::new(
+            f64::exp(-self.absorption.x() * depth),
+            f64::exp(-self.absorption.y() * depth),
+            f64::exp(-self.absorption.z() * depth),
+        )
+    }
+}
+
+impl Material for ClearCoat {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        // The coat is on the outside, so paths inside the object only meet
+        // the base
+        if !rec.front_face {
+            return self.base.scatter(r_in, rec);
+        }
+
+        let unit_direction = vec3::unit_vector(r_in.direction());
+        let cos_theta = f64::min(vec3::dot(-unit_direction, rec.normal), 1.0);
+        if Dielectric::reflectance(cos_theta, 1.0 / self.ir) > utils::random_double() {
+            return Some(ScatterRecord {
+                attenuation: Color::new(1.0, 1.0, 1.0),
+                spectral_attenuation: None,
+                scattered: r_in.spawn(rec.p, vec3::reflect(unit_direction, rec.normal)),
+                kind: ScatterKind::Specular,
+            });
+        }
+
+        let inside = vec3::refract(unit_direction, rec.normal, 1.0 / self.ir);
+        let mut ray = r_in.spawn(rec.p, inside);
+        let mut tint = self.transmittance(-vec3::dot(inside, rec.normal));
+
+        // What the base reflects, kept per wavelength on spectral paths
+        let mut attenuation = Color::new(1.0, 1.0, 1.0);
+        let mut spectral = r_in.wavelengths().map(|_| SampledSpectrum::constant(1.0));
+
+        for _ in 0..MAX_COAT_BOUNCES {
+            let base_rec = self.base.scatter(&ray, rec)?;
+            attenuation = attenuation * base_rec.attenuation;
+            if let (Some(spectral), Some(wavelengths)) =
+                (spectral.as_mut(), base_rec.scattered.wavelengths())
+            {
+                *spectral = *spectral
+                    * base_rec.spectral_attenuation.unwrap_or_else(|| {
+                        SampledSpectrum::from_rgb(base_rec.attenuation, wavelengths)
+                    });
+            }
+
+            let direction = vec3::unit_vector(base_rec.scattered.direction());
+            let cos_out = vec3::dot(direction, rec.normal);
+            let escaped = if cos_out <= 0.0 {
+                // Transmitted into the base, like light entering coated glass
+                Some(base_rec.scattered)
+            } else {
+                tint = tint * self.transmittance(cos_out);
+
+                let sin2_exit = self.ir * self.ir * (1.0 - cos_out * cos_out);
+                let cos_exit = f64::sqrt(f64::max(1.0 - sin2_exit, 0.0));
+                if sin2_exit < 1.0
+                    && Dielectric::reflectance(cos_exit, self.ir) <= utils::random_double()
+                {
+                    let exit = vec3::refract(direction, -rec.normal, self.ir);
+                    Some(base_rec.scattered.spawn(rec.p, exit))
+                } else {
+                    // Back down to the base for another bounce
+                    tint = tint * self.transmittance(cos_out);
+                    ray = base_rec
+                        .scattered
+                        .spawn(rec.p, vec3::reflect(direction, rec.normal));
+                    None
+                }
+            };
+
+            if let Some(scattered) = escaped {
+                let spectral_attenuation = match (spectral, scattered.wavelengths()) {
+                    (Some(spectral), Some(wavelengths)) => {
+                        Some(spectral * SampledSpectrum::from_rgb(tint, wavelengths))
+                    }
+                    _ => None,
+                };
+                return Some(ScatterRecord {
+                    attenuation: attenuation * tint,
+                    spectral_attenuation,
+                    scattered,
+                    kind: base_rec.kind,
+                });
+            }
+        }
+
+        None
+    }
+
+    // The base's response to light refracted in through the coat and back
+    // out, less what the coat reflects and absorbs on the way
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        if !rec.front_face {
+            return self.base.eval(r_in, rec, scattered);
+        }
+
+        let unit_in = vec3::unit_vector(r_in.direction());
+        let unit_out = vec3::unit_vector(scattered.direction());
+        let cos_in = f64::min(vec3::dot(-unit_in, rec.normal), 1.0);
+        let cos_out = vec3::dot(unit_out, rec.normal);
+        if cos_out <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let inside_in = vec3::refract(unit_in, rec.normal, 1.0 / self.ir);
+        let inside_out = -vec3::refract(-unit_out, rec.normal, 1.0 / self.ir);
+        let transmission = (1.0 - Dielectric::reflectance(cos_in, 1.0 / self.ir))
+            * (1.0 - Dielectric::reflectance(cos_out, 1.0 / self.ir));
+        let tint = self.transmittance(-vec3::dot(inside_in, rec.normal))
+            * self.transmittance(vec3::dot(inside_out, rec.normal));
+
+        let base = self.base.eval(
+            &r_in.spawn(rec.p, inside_in),
+            rec,
+            &scattered.spawn(rec.p, inside_out),
+        );
+        transmission * tint * base
+    }
+
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base.albedo(rec)
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> f64 {
+        self.base.opacity(rec)
+    }
+}
+
+// Steps a random walk takes before the path is given up as lost
+const MAX_WALK_STEPS: usize = 1024;
+
+// The end of a random walk through a subsurface volume
+pub struct WalkExit {
+    // The light the walk carries out and the ray it leaves along
+    pub scatter: ScatterRecord,
+    // The ray that reached the volume's own boundary and the hit there, seen
+    // from inside, when the light left through it. Lights are sampled through
+    // the boundary's diffuse transmission lobe at this hit. None when the
+    // walk ran into another object instead.
+    pub boundary: Option<(Ray, HitRecord)>,
+}
+
+// A smooth dielectric boundary around a scattering interior, for skin, wax,
+// marble and milk. Light refracted inside takes a random walk through the
+// volume until it reaches the boundary again, so the object must be closed.
+pub struct Subsurface {
+    // Chance of each interaction inside scattering the light rather than
+    // absorbing it
+    albedo: Color,
+    // Average distance between interactions per channel, in scene units
+    mean_free_path: Color,
+    // Henyey-Greenstein asymmetry, from -1 for back scattering through 0 for
+    // isotropic to 1 for forward scattering
+    anisotropy: f64,
+    ir: f64,
+}
+
+impl Subsurface {
+    pub fn new(
+        albedo: Color,
+        mean_free_path: Color,
+        anisotropy: f64,
+        index_of_refraction: f64,
+    ) -> Subsurface {
+        Subsurface {
+            albedo,
+            mean_free_path,
+            anisotropy: anisotropy.clamp(-0.99, 0.99),
+            ir: index_of_refraction,
+        }
+    }
+
+    // Candle wax, scattering red light furthest
+    pub fn wax() -> Subsurface {
+        Subsurface::new(
+            Color::new(0.99, 0.95, 0.8),
+            Color::new(0.05, 0.035, 0.025),
+            0.0,
+            1.45,
+        )
+    }
+
+    // Walks `r`, which has just entered the volume, to where it leaves
+    // through the boundary or meets another object. Interactions are
+    // distanced by a channel picked in proportion to the light it still
+    // carries, weighting every channel by the combined odds of all of them.
+    // At the boundary the Fresnel reflectance decides whether the light is
+    // reflected back inside; the rest leaves in a cosine distribution about
+    // the outward normal. `rays` counts the intersection tests. None when
+    // the walk runs out of steps or all its light is absorbed.
+    pub fn walk(&self, world: &dyn Hittable, r: &Ray, rays: &mut u64) -> Option<WalkExit> {
+        let (mean_free_path, albedo, count): (
+            [f64; WAVELENGTH_SAMPLES],
+            [f64; WAVELENGTH_SAMPLES],
+            usize,
+        ) = match r.wavelengths() {
+            Some(wavelengths) => {
+                let lambda = wavelengths.lambda();
+                let count = if wavelengths.secondary_terminated() {
+                    1
+                } else {
+                    WAVELENGTH_SAMPLES
+                };
+                (
+                    lambda.map(|l| spectrum::interpolate_rgb(self.mean_free_path, l)),
+                    lambda.map(|l| spectrum::interpolate_rgb(self.albedo, l)),
+                    count,
+                )
+            }
+            None => (
+                std::array::from_fn(|i| self.mean_free_path[i.min(2)]),
+                std::array::from_fn(|i| self.albedo[i.min(2)]),
+                3,
+            ),
+        };
+        let sigma_t = mean_free_path.map(|m| 1.0 / f64::max(m, 1e-6));
+        let transmittance = |d: f64| sigma_t.map(|s| f64::exp(-s * d));
+
+        // An RGB path split by dispersion carries just its own channel
+        let mut weights: [f64; WAVELENGTH_SAMPLES] = match r.channel() {
+            Some(channel) if r.wavelengths().is_none() => {
+                std::array::from_fn(|i| if i == channel { 1.0 } else { 0.0 })
+            }
+            _ => std::array::from_fn(|i| if i < count { 1.0 } else { 0.0 }),
+        };
+        let mut ray = r.spawn(r.origin(), vec3::unit_vector(r.direction()));
+        for _ in 0..MAX_WALK_STEPS {
+            *rays += 1;
+            let hit = world.hit(&ray, 0.001, f64::INFINITY);
+            let boundary = hit.as_ref().map_or(f64::INFINITY, |rec| rec.t);
+
+            let total: f64 = weights.iter().sum();
+            if total <= 0.0 {
+                return None;
+            }
+            let odds = weights.map(|w| w / total);
+            let expected = |values: [f64; WAVELENGTH_SAMPLES]| {
+                odds.iter().zip(values).map(|(p, v)| p * v).sum::<f64>()
+            };
+
+            let mut u = utils::random_double();
+            let channel = (0..count)
+                .find(|&i| {
+                    u -= odds[i];
+                    u < 0.0
+                })
+                .unwrap_or(count - 1);
+            let distance = -f64::ln(1.0 - utils::random_double()) / sigma_t[channel];
+
+            if distance >= boundary {
+                let t = transmittance(boundary);
+                let pdf = expected(t);
+                weights = std::array::from_fn(|i| weights[i] * t[i] / pdf);
+
+                let (attenuation, spectral_attenuation) = match r.wavelengths() {
+                    Some(_) => (
+                        Color::new(1.0, 1.0, 1.0),
+                        Some(SampledSpectrum::new(weights)),
+                    ),
+                    None => (Color::new(weights[0], weights[1], weights[2]), None),
+                };
+                let scatter = |scattered| ScatterRecord {
+                    attenuation,
+                    spectral_attenuation,
+                    scattered,
+                    kind: ScatterKind::Transmission,
+                };
+
+                let rec = match hit {
+                    Some(rec)
+                        if !rec.front_face
+                            && rec.mat.subsurface().is_some_and(|s| std::ptr::eq(s, self)) =>
+                    {
+                        rec
+                    }
+                    _ => {
+                        return Some(WalkExit {
+                            scatter: scatter(ray),
+                            boundary: None,
+                        })
+                    }
+                };
+
+                let (direction, kind) = dielectric_direction(&ray, &rec, self.ir);
+                if kind == ScatterKind::Specular {
+                    ray = ray.spawn(rec.p, direction);
+                    continue;
+                }
+
+                let mut direction = -rec.normal + vec3::random_unit_vector();
+                if direction.near_zero() {
+                    direction = -rec.normal;
+                }
+                return Some(WalkExit {
+                    scatter: scatter(ray.spawn(rec.p, direction)),
+                    boundary: Some((ray, rec)),
+                });
+            }
+
+            let t = transmittance(distance);
+            let pdf = expected(std::array::from_fn(|i| sigma_t[i] * t[i]));
+            weights = std::array::from_fn(|i| weights[i] * albedo[i] * sigma_t[i] * t[i] / pdf);
+
+            let direction = sample_henyey_greenstein(ray.direction(), self.anisotropy);
+            ray = ray.spawn(ray.at(distance), direction);
+        }
+
+        None
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let (direction, kind) = dielectric_direction(r_in, rec, self.ir);
+
+        Some(ScatterRecord {
+            attenuation: Color::new(1.0, 1.0, 1.0),
+            spectral_attenuation: None,
+            scattered: r_in.spawn(rec.p, direction),
+            kind,
+        })
+    }
+
+    // The white diffuse transmission lobe a walk leaves the boundary
+    // through, seen from inside. It is sampled exactly, so equals its pdf.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let pdf = self.pdf(r_in, rec, scattered);
+        Color::new(pdf, pdf, pdf)
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        if rec.front_face {
+            return 0.0;
+        }
+        let cosine = vec3::dot(-rec.normal, vec3::unit_vector(scattered.direction()));
+        f64::max(cosine, 0.0) / PI
+    }
+
+    // Entering the surface is a smooth refraction; the diffuse exit is lit
+    // through `walk` instead
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn subsurface(&self) -> Option<&Subsurface> {
+        Some(self)
+    }
+}
+
+// A unit direction scattered from `direction` by the Henyey-Greenstein phase
+// function with asymmetry `g`
+fn sample_henyey_greenstein(direction: Vec3, g: f64) -> Vec3 {
+    let u = utils::random_double();
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0 * u
+    } else {
+        let s = (1.0 - g * g) / (1.0 + g - 2.0 * g * u);
+        (1.0 + g * g - s * s) / (2.0 * g)
+    };
+    around(direction, cos_theta, 2.0 * PI * utils::random_double())
+}
+
+// The unit direction at `cos_theta` to `axis` and azimuth `phi` around it
+fn around(axis: Vec3, cos_theta: f64, phi: f64) -> Vec3 {
+    let sin_theta = f64::sqrt(f64::max(1.0 - cos_theta * cos_theta, 0.0));
+
+    let w = vec3::unit_vector(axis);
+    let a = if w.x().abs() > 0.9 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let v = vec3::unit_vector(vec3::cross(w, a));
+    let u = vec3::cross(w, v);
+
+    sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w
+}
+
+// A material cut out by an alpha mask, as for leaves, fences and decals.
+// The mask's luminance is the opacity; camera paths pass through holes at
+// random and shadows let the transparent share of the light through.
+pub struct Masked {
+    material: Arc<dyn Material>,
+    alpha: Arc<dyn Texture>,
+}
+
+impl Masked {
+    pub fn new(material: Arc<dyn Material>, alpha: Arc<dyn Texture>) -> Masked {
+        Masked { material, alpha }
+    }
+}
+
+impl Material for Masked {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.material.scatter(r_in, rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.material.eval(r_in, rec, scattered)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.material.pdf(r_in, rec, scattered)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.material.is_specular()
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.material.albedo(rec)
+    }
+
+    fn subsurface(&self) -> Option<&Subsurface> {
+        self.material.subsurface()
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> f64 {
+        color::luminance(self.alpha.value(rec.u, rec.v, rec.p)).clamp(0.0, 1.0)
+    }
+}
+
+// Different materials on the two sides of a surface, told apart by which
+// side the ray arrives from
+pub struct TwoSided {
+    front: Arc<dyn Material>,
+    back: Arc<dyn Material>,
+}
+
+impl TwoSided {
+    pub fn new(front: Arc<dyn Material>, back: Arc<dyn Material>) -> TwoSided {
+        TwoSided { front, back }
+    }
+
+    fn side(&self, rec: &HitRecord) -> &dyn Material {
+        if rec.front_face {
+            self.front.as_ref()
+        } else {
+            self.back.as_ref()
+        }
+    }
+}
+
+impl Material for TwoSided {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.side(rec).scatter(r_in, rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.side(rec).eval(r_in, rec, scattered)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.side(rec).pdf(r_in, rec, scattered)
+    }
+
+    // Light sampling is decided before the side is known
+    fn is_specular(&self) -> bool {
+        self.front.is_specular() && self.back.is_specular()
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.side(rec).albedo(rec)
+    }
+
+    fn opacity(&self, rec: &HitRecord) -> f64 {
+        self.side(rec).opacity(rec)
+    }
+}
+
+// A thin diffuse sheet like paper or a lampshade, scattering some light back
+// and letting some through to the far side, both spread over the hemisphere
+pub struct ThinSheet {
+    reflectance: Color,
+    transmittance: Color,
+}
+
+impl ThinSheet {
+    // The two should sum to at most 1 in each channel
+    pub fn new(reflectance: Color, transmittance: Color) -> ThinSheet {
+        ThinSheet {
+            reflectance,
+            transmittance,
+        }
+    }
+}
+
+impl Material for ThinSheet {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let reflect = color::luminance(self.reflectance);
+        let transmit = color::luminance(self.transmittance);
+        if reflect + transmit <= 0.0 {
+            return None;
+        }
+
+        // Picks a side by how much light goes each way, the chosen lobe
+        // weighted by the odds of picking it
+        let p = reflect / (reflect + transmit);
+        let (normal, attenuation, kind) = if p > utils::random_double() {
+            (rec.normal, self.reflectance / p, ScatterKind::Diffuse)
+        } else {
+            (
+                -rec.normal,
+                self.transmittance / (1.0 - p),
+                ScatterKind::Transmission,
+            )
+        };
+
+        let mut scatter_direction = normal + vec3::random_unit_vector();
+        if scatter_direction.near_zero() {
+            scatter_direction = normal;
+        }
+
+        Some(ScatterRecord {
+            attenuation,
+            spectral_attenuation: None,
+            scattered: r_in.spawn(rec.p, scatter_direction),
+            kind,
+        })
+    }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let cosine = vec3::dot(rec.normal, vec3::unit_vector(scattered.direction()));
+        if cosine >= 0.0 {
+            self.reflectance * (cosine / PI)
+        } else {
+            self.transmittance * (-cosine / PI)
+        }
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let reflect = color::luminance(self.reflectance);
+        let transmit = color::luminance(self.transmittance);
+        if reflect + transmit <= 0.0 {
+            return 0.0;
+        }
+
+        let p = reflect / (reflect + transmit);
+        let cosine = vec3::dot(rec.normal, vec3::unit_vector(scattered.direction()));
+        if cosine >= 0.0 {
+            p * cosine / PI
+        } else {
+            (1.0 - p) * -cosine / PI
+        }
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.reflectance
+    }
+}
+
+// Lobes a hair's light is followed through separately: reflection off the
+// surface, transmission straight through, and transmission after one
+// internal reflection. Everything after is lumped into one more lobe.
+const HAIR_LOBES: usize = 3;
+
+// Absorption of the two melanin pigments per unit concentration, across a
+// fiber of unit radius
+const EUMELANIN_ABSORPTION: [f64; 3] = [0.419, 0.697, 1.37];
+const PHEOMELANIN_ABSORPTION: [f64; 3] = [0.187, 0.4, 1.05];
+
+// Chiang et al.'s hair and fur model, a rough dielectric cylinder with a
+// pigmented interior and tilted cuticle scales. Meant for curves, whose v
+// gives the offset across the fiber.
+pub struct Hair {
+    absorption: Color,
+    ir: f64,
+    beta_m: f64,
+    beta_n: f64,
+    alpha: f64,
+    // Longitudinal variance of each lobe and the azimuthal logistic scale
+    variance: [f64; HAIR_LOBES + 1],
+    scale: f64,
+    // Sines and cosines of twice, four and eight times the scale tilt
+    sin_2k_alpha: [f64; 3],
+    cos_2k_alpha: [f64; 3],
+}
+
+impl Hair {
+    // Concentrations of the dark brown eumelanin and the reddish
+    // pheomelanin: about 0.3 for blond hair, 1.3 for brown and 8 for black
+    pub fn new(eumelanin: f64, pheomelanin: f64) -> Hair {
+        let absorption = |i: usize| {
+            eumelanin * EUMELANIN_ABSORPTION[i] + pheomelanin * PHEOMELANIN_ABSORPTION[i]
+        };
+        Hair::from_absorption(Color::new(absorption(0), absorption(1), absorption(2)))
+    }
+
+    pub fn from_absorption(absorption: Color) -> Hair {
+        Hair::build(absorption, 0.3, 0.3, 2.0)
+    }
+
+    // Roughness along and around the fiber, each in [0, 1]
+    pub fn with_roughness(self, longitudinal: f64, azimuthal: f64) -> Hair {
+        Hair::build(self.absorption, longitudinal, azimuthal, self.alpha)
+    }
+
+    // Angle in degrees the cuticle scales tilt toward the root
+    pub fn with_scale_tilt(self, degrees: f64) -> Hair {
+        Hair::build(self.absorption, self.beta_m, self.beta_n, degrees)
+    }
+
+    fn build(absorption: Color, beta_m: f64, beta_n: f64, alpha: f64) -> Hair {
+        let beta_m = beta_m.clamp(0.01, 1.0);
+        let beta_n = beta_n.clamp(0.01, 1.0);
+
+        let v = (0.726 * beta_m + 0.812 * beta_m * beta_m + 3.7 * beta_m.powi(20)).powi(2);
+        let scale = f64::sqrt(PI / 8.0)
+            * (0.265 * beta_n + 1.194 * beta_n * beta_n + 5.372 * beta_n.powi(22));
+
+        let mut sin_2k_alpha = [utils::degrees_to_radians(alpha).sin(), 0.0, 0.0];
+        let mut cos_2k_alpha = [safe_sqrt(1.0 - sin_2k_alpha[0].powi(2)), 0.0, 0.0];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+        }
+
+        Hair {
+            absorption,
+            ir: 1.55,
+            beta_m,
+            beta_n,
+            alpha,
+            variance: [v, 0.25 * v, 4.0 * v, 4.0 * v],
+            scale,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    // Sine and cosine of the angle of `w` out of the plane across the fiber,
+    // and its azimuth around the fiber
+    fn angles(r_in: &Ray, rec: &HitRecord, w: Vec3) -> (f64, f64, f64) {
+        let (across, facing) = curve::fiber_frame(rec.tangent, r_in.direction());
+        let w = vec3::unit_vector(w);
+        let sin_theta = vec3::dot(w, rec.tangent).clamp(-1.0, 1.0);
+        let phi = f64::atan2(vec3::dot(w, facing), vec3::dot(w, across));
+        (sin_theta, safe_sqrt(1.0 - sin_theta * sin_theta), phi)
+    }
+
+    // The outgoing angle shifted by the scale tilt, which pushes each lobe
+    // by a different multiple of it
+    fn tilt(&self, p: usize, sin_theta: f64, cos_theta: f64) -> (f64, f64) {
+        let (s, c) = (&self.sin_2k_alpha, &self.cos_2k_alpha);
+        match p {
+            0 => (
+                sin_theta * c[1] - cos_theta * s[1],
+                cos_theta * c[1] + sin_theta * s[1],
+            ),
+            1 => (
+                sin_theta * c[0] + cos_theta * s[0],
+                cos_theta * c[0] - sin_theta * s[0],
+            ),
+            2 => (
+                sin_theta * c[2] + cos_theta * s[2],
+                cos_theta * c[2] - sin_theta * s[2],
+            ),
+            _ => (sin_theta, cos_theta),
+        }
+    }
+
+    // Share of the light leaving through each lobe at offset h, with the
+    // angle of the refracted path inside the fiber
+    fn attenuation(&self, h: f64, sin_theta_o: f64, cos_theta_o: f64) -> ([Color; 4], f64) {
+        let sin_theta_t = sin_theta_o / self.ir;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+        let etap = f64::sqrt(self.ir * self.ir - sin_theta_o * sin_theta_o) / cos_theta_o;
+        let sin_gamma_t = (h / etap).clamp(-1.0, 1.0);
+        let cos_gamma_t = safe_sqrt(1.0 - sin_gamma_t * sin_gamma_t);
+
+        // One crossing of the pigmented interior
+        let length = 2.0 * cos_gamma_t / cos_theta_t;
+        let t = Color::new(
+            f64::exp(-self.absorption.x() * length),
+            f64::exp(-self.absorption.y() * length),
+            f64::exp(-self.absorption.z() * length),
+        );
+
+        let cos_gamma_o = safe_sqrt(1.0 - h * h);
+        let f = Dielectric::reflectance(cos_theta_o * cos_gamma_o, self.ir);
+        let mut ap = [
+            Color::new(f, f, f),
+            (1.0 - f).powi(2) * t,
+            Color::default(),
+            Color::default(),
+        ];
+        ap[2] = f * ap[1] * t;
+        let rest = |i: usize| ap[2][i] * f * t[i] / (1.0 - f * t[i]);
+        ap[3] = Color::new(rest(0), rest(1), rest(2));
+
+        (ap, sin_gamma_t.asin())
+    }
+
+    // BSDF times the cosine term and the density of sampling `direction`
+    fn evaluate(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> (Color, f64) {
+        let h = 2.0 * rec.v - 1.0;
+        let gamma_o = safe_asin(h);
+        let (sin_theta_o, cos_theta_o, phi_o) = Hair::angles(r_in, rec, -r_in.direction());
+        let (sin_theta_i, cos_theta_i, phi_i) = Hair::angles(r_in, rec, direction);
+
+        let (ap, gamma_t) = self.attenuation(h, sin_theta_o, cos_theta_o);
+        let Some(weights) = lobe_weights(&ap) else {
+            return (Color::default(), 0.0);
+        };
+
+        let phi = phi_i - phi_o;
+        let mut f = Color::default();
+        let mut pdf = 0.0;
+        for p in 0..HAIR_LOBES {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            let m = longitudinal(
+                cos_theta_i,
+                cos_theta_op.abs(),
+                sin_theta_i,
+                sin_theta_op,
+                self.variance[p],
+            );
+            let n = azimuthal(phi, p, self.scale, gamma_o, gamma_t);
+            f += m * n * ap[p];
+            pdf += m * n * weights[p];
+        }
+
+        let m = longitudinal(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.variance[HAIR_LOBES],
+        );
+        f += m / (2.0 * PI) * ap[HAIR_LOBES];
+        pdf += m / (2.0 * PI) * weights[HAIR_LOBES];
+
+        (f, pdf)
+    }
+}
+
+impl Material for Hair {
+    // Picks a lobe by its share of the light, then samples its longitudinal
+    // and azimuthal distributions
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let h = 2.0 * rec.v - 1.0;
+        let gamma_o = safe_asin(h);
+        let (sin_theta_o, cos_theta_o, phi_o) = Hair::angles(r_in, rec, -r_in.direction());
+        let (ap, gamma_t) = self.attenuation(h, sin_theta_o, cos_theta_o);
+        let weights = lobe_weights(&ap)?;
+
+        let mut choice = utils::random_double();
+        let mut p = 0;
+        while p < HAIR_LOBES && choice >= weights[p] {
+            choice -= weights[p];
+            p += 1;
+        }
+
+        let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+        let v = self.variance[p];
+        let xi = f64::max(utils::random_double(), 1e-5);
+        let cos_theta = 1.0 + v * f64::ln(xi + (1.0 - xi) * f64::exp(-2.0 / v));
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = f64::cos(2.0 * PI * utils::random_double());
+        let sin_theta_i = -cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op;
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+
+        let dphi = if p < HAIR_LOBES {
+            fiber_deflection(p, gamma_o, gamma_t)
+                + sample_trimmed_logistic(utils::random_double(), self.scale, -PI, PI)
+        } else {
+            2.0 * PI * utils::random_double()
+        };
+        let phi_i = phi_o + dphi;
+
+        let (across, facing) = curve::fiber_frame(rec.tangent, r_in.direction());
+        let direction = sin_theta_i * rec.tangent
+            + cos_theta_i * f64::cos(phi_i) * across
+            + cos_theta_i * f64::sin(phi_i) * facing;
+
+        let (f, pdf) = self.evaluate(r_in, rec, direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(ScatterRecord {
+            attenuation: f / pdf,
+            spectral_attenuation: None,
+            scattered: r_in.spawn(rec.p, direction),
+            kind: if p == 0 {
+                ScatterKind::Specular
+            } else {
+                ScatterKind::Transmission
+            },
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.evaluate(r_in, rec, scattered.direction()).0
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.evaluate(r_in, rec, scattered.direction()).1
+    }
+
+    // The color of light crossing the fiber once
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::new(
+            f64::exp(-self.absorption.x()),
+            f64::exp(-self.absorption.y()),
+            f64::exp(-self.absorption.z()),
+        )
+    }
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+    f64::sqrt(f64::max(x, 0.0))
+}
+
+fn safe_asin(x: f64) -> f64 {
+    x.clamp(-1.0, 1.0).asin()
+}
+
+// Chance of sampling each hair lobe, in proportion to its luminance
+fn lobe_weights(ap: &[Color; HAIR_LOBES + 1]) -> Option<[f64; HAIR_LOBES + 1]> {
+    let total: f64 = ap.iter().map(|&a| color::luminance(a)).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    Some(ap.map(|a| color::luminance(a) / total))
+}
+
+// Modified Bessel function of the first kind and order zero
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 0.0;
+    let mut x2i = 1.0;
+    let mut factorial = 1.0;
+    let mut four_i = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            factorial *= i as f64;
+        }
+        sum += x2i / (four_i * factorial * factorial);
+        x2i *= x * x;
+        four_i *= 4.0;
+    }
+    sum
+}
+
+fn log_bessel_i0(x: f64) -> f64 {
+    if x > 12.0 {
+        x + 0.5 * (-f64::ln(2.0 * PI) + f64::ln(1.0 / x) + 1.0 / (8.0 * x))
+    } else {
+        bessel_i0(x).ln()
+    }
+}
+
+// d'Eon et al.'s longitudinal scattering function, with variance v
+fn longitudinal(
+    cos_theta_i: f64,
+    cos_theta_o: f64,
+    sin_theta_i: f64,
+    sin_theta_o: f64,
+    v: f64,
+) -> f64 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // Worked in logarithms, which would overflow otherwise
+        f64::exp(log_bessel_i0(a) - b - 1.0 / v + std::f64::consts::LN_2 + f64::ln(1.0 / (2.0 * v)))
+    } else {
+        f64::exp(-b) * bessel_i0(a) / (f64::sinh(1.0 / v) * 2.0 * v)
+    }
+}
+
+// Change in azimuth of light leaving through lobe p
+fn fiber_deflection(p: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+    let p = p as f64;
+    2.0 * p * gamma_t - 2.0 * gamma_o + p * PI
+}
+
+// Azimuthal scattering function of lobe p, a logistic around its deflection
+fn azimuthal(phi: f64, p: usize, s: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+    let mut dphi = phi - fiber_deflection(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2.0 * PI;
+    }
+    while dphi < -PI {
+        dphi += 2.0 * PI;
+    }
+    trimmed_logistic(dphi, s, -PI, PI)
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+    let e = f64::exp(-x.abs() / s);
+    e / (s * (1.0 + e).powi(2))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+    1.0 / (1.0 + f64::exp(-x / s))
+}
+
+// The logistic distribution restricted to [a, b]
+fn trimmed_logistic(x: f64, s: f64, a: f64, b: f64) -> f64 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f64, s: f64, a: f64, b: f64) -> f64 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * f64::ln(1.0 / (u * k + logistic_cdf(a, s)) - 1.0);
+    x.clamp(a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Point3;
+
+    const SAMPLES: usize = 200_000;
+    const COS_BINS: usize = 8;
+    const PHI_BINS: usize = 16;
+
+    fn hit(mat: Arc<dyn Material>) -> HitRecord {
+        HitRecord {
+            p: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            tangent: Vec3::new(1.0, 0.0, 0.0),
+            mat,
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            object_id: 0,
+        }
+    }
+
+    // Checks that every direction `scatter` draws is weighted by eval / pdf,
+    // and that the directions land in equal solid angle bins of the upper
+    // hemisphere as often as `pdf` integrated over each bin predicts
+    fn check_pdf(mat: Arc<dyn Material>) {
+        utils::seed_random(1);
+        let rec = hit(mat.clone());
+        // Fairly grazing, so the rough diffuse models lean on their retro lobe
+        let r_in = Ray::new(Point3::new(-1.0, -0.5, 0.35), Vec3::new(1.0, 0.5, -0.35));
+        let bin = |w: Vec3| {
+            let w = vec3::unit_vector(w);
+            let phi = f64::atan2(w.y(), w.x()).rem_euclid(2.0 * PI);
+            let i = ((w.z() * COS_BINS as f64) as usize).min(COS_BINS - 1);
+            let j = ((phi / (2.0 * PI) * PHI_BINS as f64) as usize).min(PHI_BINS - 1);
+            i * PHI_BINS + j
+        };
+
+        let mut counts = [0usize; COS_BINS * PHI_BINS];
+        for _ in 0..SAMPLES {
+            let Some(scatter_rec) = mat.scatter(&r_in, &rec) else {
+                continue;
+            };
+            let scattered = scatter_rec.scattered;
+            let pdf = mat.pdf(&r_in, &rec, &scattered);
+            assert!(pdf > 0.0);
+
+            let expected = mat.eval(&r_in, &rec, &scattered) / pdf;
+            for c in 0..3 {
+                let error = (scatter_rec.attenuation[c] - expected[c]).abs();
+                assert!(
+                    error <= 1.0e-6 * expected[c].max(1.0),
+                    "weight is not eval / pdf"
+                );
+            }
+            counts[bin(scattered.direction())] += 1;
+        }
+
+        // Integrate the pdf over each bin on a fine grid
+        let steps = 16;
+        let d_cos = 1.0 / (COS_BINS * steps) as f64;
+        let d_phi = 2.0 * PI / (PHI_BINS * steps) as f64;
+        let mut predicted = [0.0; COS_BINS * PHI_BINS];
+        for a in 0..COS_BINS * steps {
+            for b in 0..PHI_BINS * steps {
+                let cos_theta = (a as f64 + 0.5) * d_cos;
+                let phi = (b as f64 + 0.5) * d_phi;
+                let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
+                let w = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                let pdf = mat.pdf(&r_in, &rec, &Ray::new(rec.p, w));
+                predicted[bin(w)] += pdf * d_cos * d_phi;
+            }
+        }
+
+        for (count, p) in counts.iter().zip(predicted) {
+            let observed = *count as f64 / SAMPLES as f64;
+            let tolerance = 5.0 * f64::sqrt(p / SAMPLES as f64) + 2.0e-3 * p + 1.0e-4;
+            assert!(
+                (observed - p).abs() < tolerance,
+                "sampled {} of directions where pdf predicts {}",
+                observed,
+                p
+            );
+        }
+    }
+
+    #[test]
+    fn ggx_pdf_matches_sampling() {
+        check_pdf(Arc::new(AnisotropicMetal::new(
+            Color::new(0.9, 0.6, 0.3),
+            0.3,
+            0.6,
+        )));
+    }
+
+    #[test]
+    fn oren_nayar_pdf_matches_sampling() {
+        check_pdf(Arc::new(OrenNayar::new(Color::new(0.8, 0.5, 0.2), 1.0)));
+    }
+
+    #[test]
+    fn energy_preserving_oren_nayar_pdf_matches_sampling() {
+        check_pdf(Arc::new(RoughDiffuse::new(Color::new(0.8, 0.5, 0.2), 1.0)));
+    }
 }
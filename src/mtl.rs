@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::color::Color;
+use crate::material::{Lambertian, Masked, Material};
+use crate::texture::{ImageTexture, SolidColor};
+
+// One `newmtl` block of a material library, as far as it has been read
+struct MtlEntry {
+    name: String,
+    diffuse: Color,
+    dissolve: f64,
+    alpha_map: Option<String>,
+}
+
+impl MtlEntry {
+    fn new(name: &str) -> MtlEntry {
+        MtlEntry {
+            name: name.to_string(),
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            dissolve: 1.0,
+            alpha_map: None,
+        }
+    }
+
+    fn build(self, dir: &Path) -> io::Result<(String, Arc<dyn Material>)> {
+        let base: Arc<dyn Material> = Arc::new(Lambertian::new(self.diffuse));
+        let material: Arc<dyn Material> = if let Some(file) = &self.alpha_map {
+            let path = dir.join(file);
+            let mask = ImageTexture::load(&path.to_string_lossy())?;
+            Arc::new(Masked::new(base, Arc::new(mask)))
+        } else if self.dissolve < 1.0 {
+            let d = self.dissolve.max(0.0);
+            Arc::new(Masked::new(
+                base,
+                Arc::new(SolidColor::new(Color::new(d, d, d))),
+            ))
+        } else {
+            base
+        };
+        Ok((self.name, material))
+    }
+}
+
+// Reads the materials of a Wavefront MTL library by name. Only the diffuse
+// color `Kd` and opacity are understood: a `map_d` image becomes the alpha
+// mask of a `Masked` material, and without one a `d` dissolve (or `Tr`, its
+// complement) below 1 gives a uniform mask. Image paths are relative to the
+// library. There is no OBJ geometry loader, so objects are handed these
+// materials by name.
+pub fn load(path: &str) -> io::Result<HashMap<String, Arc<dyn Material>>> {
+    let text = fs::read_to_string(path)?;
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    parse(&text, dir)
+}
+
+pub fn parse(text: &str, dir: &Path) -> io::Result<HashMap<String, Arc<dyn Material>>> {
+    let invalid = |line: usize, message: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", line + 1, message),
+        )
+    };
+
+    let mut materials = HashMap::new();
+    let mut current: Option<MtlEntry> = None;
+
+    for (line, text) in text.lines().enumerate() {
+        let mut tokens = text.split_whitespace();
+        let Some(keyword) = tokens.next().filter(|k| !k.starts_with('#')) else {
+            continue;
+        };
+        let args: Vec<&str> = tokens.collect();
+        let number = |i: usize| -> io::Result<f64> {
+            args.get(i)
+                .and_then(|arg| arg.parse().ok())
+                .ok_or_else(|| invalid(line, &format!("invalid value for {}", keyword)))
+        };
+
+        if keyword == "newmtl" {
+            let name = args
+                .first()
+                .ok_or_else(|| invalid(line, "newmtl without a name"))?;
+            if let Some(entry) = current.replace(MtlEntry::new(name)) {
+                let (name, material) = entry.build(dir)?;
+                materials.insert(name, material);
+            }
+            continue;
+        }
+
+        let Some(entry) = current.as_mut() else {
+            return Err(invalid(line, &format!("{} before any newmtl", keyword)));
+        };
+        match keyword {
+            "Kd" => entry.diffuse = Color::new(number(0)?, number(1)?, number(2)?),
+            "d" => entry.dissolve = number(0)?,
+            "Tr" => entry.dissolve = 1.0 - number(0)?,
+            // Options such as -clamp come before the file name
+            "map_d" => {
+                let file = args
+                    .last()
+                    .ok_or_else(|| invalid(line, "map_d without a file"))?;
+                entry.alpha_map = Some(file.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(entry) = current {
+        let (name, material) = entry.build(dir)?;
+        materials.insert(name, material);
+    }
+
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HitRecord;
+    use crate::vec3::{Point3, Vec3};
+
+    fn opacity(material: &Arc<dyn Material>, u: f64) -> f64 {
+        let rec = HitRecord {
+            p: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            tangent: Vec3::new(1.0, 0.0, 0.0),
+            mat: material.clone(),
+            t: 1.0,
+            u,
+            v: 0.5,
+            front_face: true,
+            object_id: 0,
+        };
+        material.opacity(&rec)
+    }
+
+    #[test]
+    fn map_d_and_dissolve_become_alpha_masks() {
+        let dir = std::env::temp_dir().join(format!("raytracer-mtl-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // A hole on the left, opaque on the right
+        fs::write(
+            dir.join("leaf_alpha.ppm"),
+            "P3\n2 1\n255\n0 0 0 255 255 255\n",
+        )
+        .unwrap();
+
+        let materials = parse(
+            "# cutout and see-through materials\n\
+             newmtl leaf\n\
+             Kd 0.2 0.6 0.1\n\
+             map_d -clamp on leaf_alpha.ppm\n\
+             \n\
+             newmtl tinted\n\
+             Tr 0.75\n\
+             newmtl solid\n\
+             Kd 1 0 0\n",
+            &dir,
+        )
+        .unwrap();
+        fs::remove_dir_all(&dir).ok();
+
+        let leaf = &materials["leaf"];
+        assert!(opacity(leaf, 0.25).abs() < 1.0e-6);
+        assert!((opacity(leaf, 0.75) - 1.0).abs() < 1.0e-6);
+        assert!((opacity(&materials["tinted"], 0.5) - 0.25).abs() < 1.0e-6);
+        assert_eq!(opacity(&materials["solid"], 0.5), 1.0);
+    }
+}
@@ -1,14 +1,52 @@
+use crate::spectrum::SampledWavelengths;
 use crate::vec3::{Point3, Vec3};
 
-#[derive(Default)]
+#[derive(Clone, Copy, Default)]
 pub struct Ray {
     origin: Point3,
     direction: Vec3,
+    time: f64,
+    // Set on paths traced in spectral mode
+    wavelengths: Option<SampledWavelengths>,
+    // The one color channel an RGB path follows after splitting at a
+    // dispersive surface
+    channel: Option<usize>,
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Self {
-        Ray { origin, direction }
+        Ray::with_time(origin, direction, 0.0)
+    }
+
+    // `time` is in seconds since the shutter opened
+    pub fn with_time(origin: Point3, direction: Vec3, time: f64) -> Self {
+        Ray {
+            origin,
+            direction,
+            time,
+            wavelengths: None,
+            channel: None,
+        }
+    }
+
+    pub fn with_wavelengths(mut self, wavelengths: SampledWavelengths) -> Self {
+        self.wavelengths = Some(wavelengths);
+        self
+    }
+
+    pub fn with_channel(mut self, channel: usize) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    // A ray from `origin` along `direction` at the same time and wavelengths
+    // as this one, for bounces, shadow rays and changes of space
+    pub fn spawn(&self, origin: Point3, direction: Vec3) -> Self {
+        Ray {
+            origin,
+            direction,
+            ..*self
+        }
     }
 
     pub fn origin(&self) -> Point3 {
@@ -19,6 +57,18 @@ impl Ray {
         self.direction
     }
 
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn wavelengths(&self) -> Option<&SampledWavelengths> {
+        self.wavelengths.as_ref()
+    }
+
+    pub fn channel(&self) -> Option<usize> {
+        self.channel
+    }
+
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + t * self.direction
     }
@@ -0,0 +1,630 @@
+use std::ops::{AddAssign, DivAssign, Mul};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::adaptive::{AdaptiveSettings, SampleMap};
+use crate::aov::{AovBuffers, AovPixel, AovSample};
+use crate::camera::Camera;
+use crate::checkpoint::RenderState;
+use crate::color::{self, Color};
+use crate::exposure::{self, Exposure};
+use crate::filter::Filter;
+use crate::hittable;
+use crate::material::{ScatterKind, ScatterRecord};
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::{SampledSpectrum, SampledWavelengths};
+use crate::tile::{self, Tile, TileOrder};
+use crate::utils;
+use crate::vec3;
+
+#[derive(Clone, Copy)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: u32,
+    pub samples_per_pass: u32,
+    pub adaptive: AdaptiveSettings,
+    pub filter: Filter,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    pub max_depth: u32,
+    pub max_diffuse_depth: u32,
+    pub max_specular_depth: u32,
+    pub max_transmission_depth: u32,
+    pub russian_roulette_depth: u32,
+    // Physical camera exposure, or None to keep scene values as they are.
+    // Its shutter also sets the motion blur interval.
+    pub exposure: Option<Exposure>,
+    // Color temperature in kelvin that comes out white
+    pub white_balance: Option<f64>,
+    // Trace sampled wavelengths instead of RGB
+    pub spectral: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 800,
+            height: 450,
+            samples_per_pixel: 100,
+            samples_per_pass: 10,
+            adaptive: AdaptiveSettings::disabled(),
+            filter: Filter::default(),
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            max_depth: 50,
+            max_diffuse_depth: 16,
+            max_specular_depth: 32,
+            max_transmission_depth: 32,
+            russian_roulette_depth: 5,
+            exposure: None,
+            white_balance: None,
+            spectral: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderProgress {
+    pub fraction: f64,
+    pub tiles_done: usize,
+    pub total_tiles: usize,
+    // Camera, bounce and shadow rays traced so far
+    pub rays: u64,
+    pub elapsed: Duration,
+}
+
+impl RenderProgress {
+    // Extrapolated from the time per tile so far, so adaptive renders that
+    // stop early finish sooner than estimated
+    pub fn eta(&self) -> Option<Duration> {
+        if self.tiles_done == 0 {
+            return None;
+        }
+
+        let remaining = self.total_tiles.saturating_sub(self.tiles_done);
+        Some(
+            self.elapsed
+                .mul_f64(remaining as f64 / self.tiles_done as f64),
+        )
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        self.rays as f64 / f64::max(self.elapsed.as_secs_f64(), 1.0e-9)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RenderStatus {
+    Completed,
+    Cancelled,
+}
+
+// A cheaply cloneable flag for stopping a render from another thread. Tiles
+// already being rendered are finished, the rest are skipped.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// Hooks for embedding applications. All of them are called from render
+// threads, so implementations must be cheap and thread safe.
+pub trait RenderObserver: Sync {
+    // `bounds` covers every film pixel the tile changed and `pixels` holds
+    // their resolved colors, top row first. The film is not locked meanwhile.
+    fn tile_finished(&self, _bounds: Tile, _pixels: &[Color]) {}
+
+    fn pass_finished(&self, _state: &RenderState) {}
+
+    fn progress(&self, _progress: &RenderProgress) {}
+
+    // Checked before each tile and after each pass
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+impl RenderObserver for () {}
+
+impl RenderObserver for CancellationToken {
+    fn is_cancelled(&self) -> bool {
+        CancellationToken::is_cancelled(self)
+    }
+}
+
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    // Linear colors, top row first
+    pub pixels: Vec<Color>,
+    // False for the partial result of a cancelled render
+    pub completed: bool,
+    pub aovs: AovBuffers,
+    pub samples: SampleMap,
+}
+
+impl RenderState {
+    pub fn into_framebuffer(self) -> Framebuffer {
+        let film = self.film.into_inner().unwrap();
+
+        Framebuffer {
+            width: film.width(),
+            height: film.height(),
+            pixels: film.image(),
+            completed: self.samples_done == self.samples_per_pixel,
+            aovs: self.aovs.into_inner().unwrap(),
+            samples: self.stats.into_inner().unwrap(),
+        }
+    }
+}
+
+pub struct Renderer {
+    settings: RenderSettings,
+}
+
+impl Renderer {
+    pub fn new(settings: RenderSettings) -> Renderer {
+        Renderer { settings }
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    pub fn new_state(&self, seed: u64) -> RenderState {
+        RenderState::new(self.settings, seed)
+    }
+
+    pub fn render(&self, scene: &Scene, camera: &dyn Camera) -> Framebuffer {
+        self.render_with(scene, camera, &())
+    }
+
+    // Renders with a fresh state, returning whatever was finished if the
+    // observer cancels
+    pub fn render_with(
+        &self,
+        scene: &Scene,
+        camera: &dyn Camera,
+        observer: &impl RenderObserver,
+    ) -> Framebuffer {
+        let mut state = self.new_state(utils::random_seed());
+        self.render_state(scene, camera, &mut state, observer);
+        state.into_framebuffer()
+    }
+
+    // Renders the samples `state` is still missing, in passes of
+    // `samples_per_pass`, handing tiles out to the rayon pool in order. The
+    // image size, filter, sample count and adaptive settings are taken from
+    // `state`, so resumed renders match the original. If the observer cancels,
+    // the render stops early. A pass interrupted that way is left out
+    // of `samples_done`, since only some of its tiles ran, but the tiles that
+    // did finish are kept in the film.
+    pub fn render_state(
+        &self,
+        scene: &Scene,
+        camera: &dyn Camera,
+        state: &mut RenderState,
+        observer: &impl RenderObserver,
+    ) -> RenderStatus {
+        let start = Instant::now();
+        let tiles = self.tiles(state);
+        let total_tiles = tiles.len() * self.pass_count(state) as usize;
+        let tiles_done = AtomicUsize::new(0);
+        let rays = AtomicU64::new(0);
+
+        let progress = |tiles_done: usize| RenderProgress {
+            fraction: tiles_done as f64 / total_tiles as f64,
+            tiles_done,
+            total_tiles,
+            rays: rays.load(Ordering::Relaxed),
+            elapsed: start.elapsed(),
+        };
+
+        while state.samples_done < state.samples_per_pixel {
+            let pass_end = u32::min(
+                state.samples_done + self.settings.samples_per_pass,
+                state.samples_per_pixel,
+            );
+
+            tiles.iter().par_bridge().for_each(|tile| {
+                if observer.is_cancelled() {
+                    return;
+                }
+
+                let (bounds, tile_rays) = self.render_tile(tile, pass_end, scene, camera, state);
+                rays.fetch_add(tile_rays, Ordering::Relaxed);
+                let pixels = state.film.lock().unwrap().resolve(&bounds);
+                observer.tile_finished(bounds, &pixels);
+
+                let done = tiles_done.fetch_add(1, Ordering::Relaxed) + 1;
+                observer.progress(&progress(done));
+            });
+
+            if observer.is_cancelled() {
+                return RenderStatus::Cancelled;
+            }
+            state.samples_done = pass_end;
+
+            let stats = state.stats.get_mut().unwrap();
+            if stats.converged(state.samples_per_pixel, &state.adaptive) {
+                state.samples_done = state.samples_per_pixel;
+                let done = tiles_done.load(Ordering::Relaxed);
+                observer.progress(&RenderProgress {
+                    fraction: 1.0,
+                    total_tiles: done,
+                    ..progress(done)
+                });
+            }
+
+            observer.pass_finished(state);
+        }
+
+        RenderStatus::Completed
+    }
+
+    // Seconds the shutter stays open for each frame
+    pub fn shutter(&self) -> f64 {
+        self.settings
+            .exposure
+            .map_or(0.0, |exposure| exposure.shutter)
+    }
+
+    // Per-channel factor taking scene values to image values
+    fn sensor_response(&self) -> Color {
+        let scale = self
+            .settings
+            .exposure
+            .map_or(1.0, |exposure| exposure.scale());
+        match self.settings.white_balance {
+            Some(temperature) => scale * exposure::white_balance(temperature),
+            None => Color::new(scale, scale, scale),
+        }
+    }
+
+    pub fn pass_count(&self, state: &RenderState) -> u32 {
+        (state.samples_per_pixel - state.samples_done).div_ceil(self.settings.samples_per_pass)
+    }
+
+    fn tiles(&self, state: &RenderState) -> Vec<Tile> {
+        let film = state.film.lock().unwrap();
+        tile::generate_tiles(
+            film.width(),
+            film.height(),
+            self.settings.tile_size,
+            self.settings.tile_order,
+        )
+    }
+
+    // Brings every pixel in `tile` that still needs samples up to `pass_end`
+    // samples, splatting them into a film tile of its own. The film tile,
+    // AOVs and sample statistics are then merged into the shared buffers.
+    // Returns the film bounds that changed and the number of rays traced.
+    fn render_tile(
+        &self,
+        tile: &Tile,
+        pass_end: u32,
+        scene: &Scene,
+        camera: &dyn Camera,
+        state: &RenderState,
+    ) -> (Tile, u64) {
+        let (mut film_tile, width, height) = {
+            let film = state.film.lock().unwrap();
+            (film.tile(tile), film.width(), film.height())
+        };
+
+        let mut aov_pixels = Vec::with_capacity(tile.pixel_count());
+        let mut pixel_stats = Vec::with_capacity(tile.pixel_count());
+        let mut rays = 0;
+        let response = self.sensor_response();
+        let shutter = self.shutter();
+
+        {
+            let stats = state.stats.lock().unwrap();
+            for y in tile.y0..tile.y1 {
+                for x in tile.x0..tile.x1 {
+                    pixel_stats.push(*stats.pixel(x, y));
+                }
+            }
+        }
+
+        let mut stats = pixel_stats.iter_mut();
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                let mut aov_pixel = AovPixel::default();
+                let stats = stats.next().unwrap();
+
+                if !state.adaptive.needs_samples(stats) {
+                    aov_pixels.push(aov_pixel);
+                    continue;
+                }
+
+                for sample in stats.samples()..pass_end {
+                    let pixel = y * width + x;
+                    utils::seed_random(utils::sample_seed(state.seed, pixel, sample));
+
+                    let film_x = x as f64 + utils::random_double();
+                    let film_y = y as f64 + utils::random_double();
+                    let u = film_x / (width - 1) as f64;
+                    let v = (height as f64 - film_y) / (height - 1) as f64;
+                    let time = if shutter > 0.0 {
+                        shutter * utils::random_double()
+                    } else {
+                        0.0
+                    };
+                    let (color, mut aov) = match camera.get_ray(u, v) {
+                        Some(r) if self.settings.spectral => {
+                            let wavelengths = SampledWavelengths::sample(utils::random_double());
+                            let r = Ray::with_time(r.origin(), r.direction(), time)
+                                .with_wavelengths(wavelengths);
+                            self.ray_color::<SampledSpectrum>(&r, scene, &mut rays)
+                        }
+                        Some(r) => {
+                            let r = Ray::with_time(r.origin(), r.direction(), time);
+                            self.ray_color::<Color>(&r, scene, &mut rays)
+                        }
+                        // Blocked by the lens or outside the image, which
+                        // darkens the pixel by the fraction of samples lost
+                        None => (Color::new(0.0, 0.0, 0.0), AovSample::default()),
+                    };
+                    let color = response * color;
+                    aov.direct = response * aov.direct;
+                    aov.indirect = response * aov.indirect;
+                    film_tile.add_sample(film_x, film_y, color);
+                    aov_pixel.add(&aov);
+                    stats.add(color::luminance(color));
+                }
+
+                aov_pixels.push(aov_pixel);
+            }
+        }
+
+        state.film.lock().unwrap().merge_tile(&film_tile);
+
+        let mut aovs = state.aovs.lock().unwrap();
+        let mut stats = state.stats.lock().unwrap();
+        let mut pixels = aov_pixels.iter().zip(&pixel_stats);
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                let (aov_pixel, pixel_stats) = pixels.next().unwrap();
+                aovs.pixel_mut(x, y).merge(aov_pixel);
+                *stats.pixel_mut(x, y) = *pixel_stats;
+            }
+        }
+
+        (film_tile.bounds(), rays)
+    }
+
+    fn ray_color<R: Radiance>(&self, r: &Ray, scene: &Scene, rays: &mut u64) -> (Color, AovSample) {
+        let world = scene.world.as_ref();
+        let lights = &scene.lights;
+
+        let mut aov = AovSample::default();
+        let mut color = R::zero();
+        let mut direct = R::zero();
+        let mut throughput = R::one();
+        let mut ray = *r;
+
+        let mut diffuse_depth = 0;
+        let mut specular_depth = 0;
+        let mut transmission_depth = 0;
+
+        for depth in 0..self.settings.max_depth {
+            *rays += 1;
+            let Some(hit_rec) = hittable::hit_opaque(world, &ray, 0.001, f64::INFINITY) else {
+                let background = scene.background(&ray);
+                color += throughput * R::from_rgb(background, &ray);
+                if depth == 0 {
+                    aov.albedo = background;
+                    direct = color;
+                }
+                break;
+            };
+
+            if depth == 0 {
+                aov.albedo = hit_rec.mat.albedo(&hit_rec);
+                aov.normal = hit_rec.normal;
+                aov.position = hit_rec.p;
+                aov.depth = hit_rec.t * ray.direction().length();
+                aov.object_id = Some(hit_rec.object_id);
+            }
+
+            if !lights.is_empty() && !hit_rec.mat.is_specular() {
+                *rays += lights.len() as u64;
+                let light = lights.direct_lighting(world, &ray, &hit_rec);
+                color += throughput * R::from_rgb(light, &ray);
+                if depth == 0 {
+                    direct = color;
+                }
+            }
+
+            let Some(scatter_rec) = hit_rec.mat.scatter(&ray, &hit_rec) else {
+                break;
+            };
+
+            let (bounces, max_bounces) = match scatter_rec.kind {
+                ScatterKind::Diffuse => (&mut diffuse_depth, self.settings.max_diffuse_depth),
+                ScatterKind::Specular => (&mut specular_depth, self.settings.max_specular_depth),
+                ScatterKind::Transmission => (
+                    &mut transmission_depth,
+                    self.settings.max_transmission_depth,
+                ),
+            };
+            *bounces += 1;
+            if *bounces > max_bounces {
+                break;
+            }
+
+            throughput = throughput * R::attenuation(&scatter_rec);
+            let mut scattered = scatter_rec.scattered;
+
+            // Light refracted into a scattering interior wanders through it
+            // before reaching the boundary again
+            if let Some(subsurface) = hit_rec.mat.subsurface() {
+                let outward_normal = if hit_rec.front_face {
+                    hit_rec.normal
+                } else {
+                    -hit_rec.normal
+                };
+                if vec3::dot(scattered.direction(), outward_normal) < 0.0 {
+                    let Some(walk) = subsurface.walk(world, &scattered, rays) else {
+                        break;
+                    };
+                    throughput = throughput * R::attenuation(&walk.scatter);
+
+                    // The light leaves diffusely, so lights shine in through
+                    // the exit
+                    if let Some((r_exit, exit)) = &walk.boundary {
+                        if !lights.is_empty() {
+                            *rays += lights.len() as u64;
+                            let light = lights.direct_lighting(world, r_exit, exit);
+                            color += throughput * R::from_rgb(light, &ray);
+                            if depth == 0 {
+                                direct = color;
+                            }
+                        }
+                    }
+                    scattered = walk.scatter.scattered;
+                }
+            }
+
+            if depth >= self.settings.russian_roulette_depth {
+                let survival = f64::min(throughput.max_component(), 0.95);
+                if utils::random_double() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+
+            ray = scattered;
+        }
+
+        // The wavelengths the path ended with decide which of them count
+        let color = color.to_rgb(&ray);
+        aov.direct = direct.to_rgb(&ray);
+        aov.indirect = color - aov.direct;
+        (color, aov)
+    }
+}
+
+// What a path carries: RGB colors, or radiance at the wavelengths its rays
+// sample in spectral mode. RGB inputs like albedos and lights are uplifted
+// as the path meets them.
+trait Radiance: Copy + Mul<Output = Self> + AddAssign + DivAssign<f64> {
+    fn zero() -> Self;
+
+    fn one() -> Self;
+
+    fn from_rgb(color: Color, ray: &Ray) -> Self;
+
+    fn attenuation(scatter_rec: &ScatterRecord) -> Self;
+
+    fn max_component(&self) -> f64;
+
+    fn to_rgb(self, ray: &Ray) -> Color;
+}
+
+impl Radiance for Color {
+    fn zero() -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    fn one() -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    fn from_rgb(color: Color, _ray: &Ray) -> Color {
+        color
+    }
+
+    fn attenuation(scatter_rec: &ScatterRecord) -> Color {
+        scatter_rec.attenuation
+    }
+
+    fn max_component(&self) -> f64 {
+        Color::max_component(self)
+    }
+
+    fn to_rgb(self, _ray: &Ray) -> Color {
+        self
+    }
+}
+
+impl Radiance for SampledSpectrum {
+    fn zero() -> SampledSpectrum {
+        SampledSpectrum::constant(0.0)
+    }
+
+    fn one() -> SampledSpectrum {
+        SampledSpectrum::constant(1.0)
+    }
+
+    fn from_rgb(color: Color, ray: &Ray) -> SampledSpectrum {
+        SampledSpectrum::from_rgb(color, ray.wavelengths().unwrap())
+    }
+
+    fn attenuation(scatter_rec: &ScatterRecord) -> SampledSpectrum {
+        scatter_rec.spectral_attenuation.unwrap_or_else(|| {
+            <Self as Radiance>::from_rgb(scatter_rec.attenuation, &scatter_rec.scattered)
+        })
+    }
+
+    fn max_component(&self) -> f64 {
+        SampledSpectrum::max_component(self)
+    }
+
+    fn to_rgb(self, ray: &Ray) -> Color {
+        ray.wavelengths().unwrap().to_rgb(&self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::light::{LightList, PointLight};
+    use crate::material::Subsurface;
+    use crate::sphere::Sphere;
+    use crate::vec3::{Point3, Vec3};
+
+    #[test]
+    fn subsurface_is_lit_by_point_lights() {
+        let mut lights = LightList::new();
+        lights.add(Box::new(PointLight::new(
+            Point3::new(4.0, 4.0, 4.0),
+            Color::new(50.0, 50.0, 50.0),
+        )));
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, Arc::new(Subsurface::wax()));
+        let mut scene = Scene::new(Box::new(sphere), lights);
+        scene.background_intensity = 0.0;
+
+        let renderer = Renderer::new(RenderSettings::default());
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rays = 0;
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        utils::seed_random(1);
+        for _ in 0..256 {
+            total += renderer.ray_color::<Color>(&r, &scene, &mut rays).0;
+        }
+
+        assert!(color::luminance(total / 256.0) > 0.01);
+    }
+}
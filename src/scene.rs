@@ -0,0 +1,29 @@
+use crate::color::Color;
+use crate::hittable::Hittable;
+use crate::light::LightList;
+use crate::ray::Ray;
+use crate::vec3;
+
+pub struct Scene {
+    pub world: Box<dyn Hittable>,
+    pub lights: LightList,
+    // Multiplies the sky, to light scenes in physical units
+    pub background_intensity: f64,
+}
+
+impl Scene {
+    pub fn new(world: Box<dyn Hittable>, lights: LightList) -> Scene {
+        Scene {
+            world,
+            lights,
+            background_intensity: 1.0,
+        }
+    }
+
+    pub fn background(&self, r: &Ray) -> Color {
+        let unit_direction = vec3::unit_vector(r.direction());
+        let t = 0.5 * (unit_direction.y() + 1.0);
+        self.background_intensity
+            * ((1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0))
+    }
+}
@@ -0,0 +1,483 @@
+use std::ops::{AddAssign, DivAssign, Index, Mul};
+use std::sync::OnceLock;
+
+use crate::color::Color;
+
+// Visible range the spectral renderer samples, in nanometers
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+
+// Wavelengths standing in for the red, green and blue channels when RGB
+// paths meet wavelength dependent materials
+pub const RGB_WAVELENGTHS: [f64; 3] = [610.0, 550.0, 465.0];
+
+// Value at `lambda` nanometers of a quantity given per color channel, read
+// linearly between the channels' stand-in wavelengths and held beyond them.
+// Unlike an uplift it works for values of any size, like distances.
+pub fn interpolate_rgb(color: Color, lambda: f64) -> f64 {
+    let [red, green, blue] = RGB_WAVELENGTHS;
+    if lambda <= blue {
+        color.z()
+    } else if lambda <= green {
+        let u = (lambda - blue) / (green - blue);
+        (1.0 - u) * color.z() + u * color.y()
+    } else if lambda <= red {
+        let u = (lambda - green) / (red - green);
+        (1.0 - u) * color.y() + u * color.x()
+    } else {
+        color.x()
+    }
+}
+
+// Wavelengths traced together along each path
+pub const WAVELENGTH_SAMPLES: usize = 4;
+
+// Wavelengths carried by a spectral path. The first is the hero wavelength,
+// the others are spread evenly from it across the visible range.
+#[derive(Clone, Copy, Debug)]
+pub struct SampledWavelengths {
+    lambda: [f64; WAVELENGTH_SAMPLES],
+    // Set once something like dispersion sends each wavelength its own way,
+    // leaving only the hero wavelength to follow the path
+    secondary_terminated: bool,
+}
+
+impl SampledWavelengths {
+    // `u` is a uniform random number placing the hero wavelength
+    pub fn sample(u: f64) -> SampledWavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let lambda = std::array::from_fn(|i| {
+            let offset = (u + i as f64 / WAVELENGTH_SAMPLES as f64).fract();
+            LAMBDA_MIN + offset * range
+        });
+
+        SampledWavelengths {
+            lambda,
+            secondary_terminated: false,
+        }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    pub fn lambda(&self) -> &[f64; WAVELENGTH_SAMPLES] {
+        &self.lambda
+    }
+
+    pub fn terminate_secondary(&mut self) {
+        self.secondary_terminated = true;
+    }
+
+    pub fn secondary_terminated(&self) -> bool {
+        self.secondary_terminated
+    }
+
+    // Estimates the linear sRGB color of radiance sampled at these
+    // wavelengths, going through CIE XYZ
+    pub fn to_rgb(&self, radiance: &SampledSpectrum) -> Color {
+        let tables = tables();
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+
+        let count = if self.secondary_terminated {
+            1
+        } else {
+            WAVELENGTH_SAMPLES
+        };
+
+        let mut xyz = [0.0; 3];
+        for i in 0..count {
+            let matching = cie_xyz(self.lambda[i]);
+            for (c, m) in xyz.iter_mut().zip(matching) {
+                *c += radiance.values[i] * m / pdf;
+            }
+        }
+
+        let scale = 1.0 / (count as f64 * tables.y_integral);
+        let rgb = xyz_to_rgb(xyz.map(|c| c * scale));
+        Color::new(
+            rgb[0] / tables.white[0],
+            rgb[1] / tables.white[1],
+            rgb[2] / tables.white[2],
+        )
+    }
+}
+
+// A spectrum's values at a path's sampled wavelengths
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SampledSpectrum {
+    values: [f64; WAVELENGTH_SAMPLES],
+}
+
+impl SampledSpectrum {
+    pub fn new(values: [f64; WAVELENGTH_SAMPLES]) -> SampledSpectrum {
+        SampledSpectrum { values }
+    }
+
+    pub fn constant(value: f64) -> SampledSpectrum {
+        SampledSpectrum::new([value; WAVELENGTH_SAMPLES])
+    }
+
+    // Samples the spectrum uplifted from an RGB color
+    pub fn from_rgb(color: Color, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        RgbSpectrum::new(color).sample(wavelengths)
+    }
+
+    pub fn max_component(&self) -> f64 {
+        self.values.iter().copied().fold(f64::MIN, f64::max)
+    }
+}
+
+impl Index<usize> for SampledSpectrum {
+    type Output = f64;
+
+    fn index(&self, index: usize) -> &f64 {
+        &self.values[index]
+    }
+}
+
+impl AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, other: SampledSpectrum) {
+        for (a, b) in self.values.iter_mut().zip(other.values) {
+            *a += b;
+        }
+    }
+}
+
+impl DivAssign<f64> for SampledSpectrum {
+    fn div_assign(&mut self, t: f64) {
+        for a in &mut self.values {
+            *a /= t;
+        }
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn mul(self, other: SampledSpectrum) -> SampledSpectrum {
+        SampledSpectrum::new(std::array::from_fn(|i| self.values[i] * other.values[i]))
+    }
+}
+
+impl Mul<SampledSpectrum> for f64 {
+    type Output = SampledSpectrum;
+
+    fn mul(self, s: SampledSpectrum) -> SampledSpectrum {
+        SampledSpectrum::new(s.values.map(|v| self * v))
+    }
+}
+
+// A smooth spectrum reproducing an RGB color, after Jakob and Hanika's "A
+// Low-Dimensional Function Space for Efficient Spectral Upsampling": a
+// sigmoid of a quadratic in wavelength, scaled up for colors brighter than 1
+#[derive(Clone, Copy, Debug)]
+pub struct RgbSpectrum {
+    coefficients: [f64; 3],
+    scale: f64,
+}
+
+impl RgbSpectrum {
+    pub fn new(color: Color) -> RgbSpectrum {
+        let rgb = [color.x(), color.y(), color.z()].map(|c| c.max(0.0));
+        let max = rgb[0].max(rgb[1]).max(rgb[2]);
+
+        // Reflectances fit directly. Brighter colors, like lights, are
+        // halved into the range the sigmoid covers comfortably.
+        let scale = if max > 1.0 { 2.0 * max } else { 1.0 };
+        RgbSpectrum {
+            coefficients: tables().lookup(rgb.map(|c| c / scale)),
+            scale,
+        }
+    }
+
+    pub fn evaluate(&self, lambda: f64) -> f64 {
+        self.scale * sigmoid_polynomial(&self.coefficients, lambda)
+    }
+
+    pub fn sample(&self, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        SampledSpectrum::new(wavelengths.lambda.map(|lambda| self.evaluate(lambda)))
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    if x.is_infinite() {
+        return if x > 0.0 { 1.0 } else { 0.0 };
+    }
+    0.5 + x / (2.0 * f64::sqrt(1.0 + x * x))
+}
+
+// The quadratic runs over wavelengths mapped onto [0, 1], which keeps the
+// coefficients well conditioned
+fn sigmoid_polynomial(c: &[f64; 3], lambda: f64) -> f64 {
+    let x = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN);
+    sigmoid((c[0] * x + c[1]) * x + c[2])
+}
+
+// CIE 1931 2° color matching functions, from the multi-lobe Gaussian fit in
+// Wyman, Sloan and Shirley's "Simple Analytic Approximations to the CIE XYZ
+// Color Matching Functions"
+pub fn cie_xyz(lambda: f64) -> [f64; 3] {
+    let g = |mu: f64, sigma_below: f64, sigma_above: f64| {
+        let sigma = if lambda < mu {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (lambda - mu) / sigma;
+        f64::exp(-0.5 * t * t)
+    };
+
+    [
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    ]
+}
+
+pub fn xyz_to_rgb(xyz: [f64; 3]) -> [f64; 3] {
+    let [x, y, z] = xyz;
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+}
+
+// Spacing of the wavelengths the uplift fits integrate over
+const FIT_STEP: f64 = 5.0;
+
+// Grid points per axis of the coefficient table
+const TABLE_RESOLUTION: usize = 16;
+
+const FIT_ITERATIONS: usize = 20;
+
+// Scenes are lit by the equal energy illuminant, so a constant spectrum of 1
+// comes out white once its color is divided by `white`
+struct SpectralTables {
+    fit_lambda: Vec<f64>,
+    fit_matching: Vec<[f64; 3]>,
+    y_integral: f64,
+    white: [f64; 3],
+    z_values: [f64; TABLE_RESOLUTION],
+    // Sigmoid coefficients indexed by the largest channel, then by the value
+    // of that channel and the ratios of the next two to it
+    coefficients: Vec<[f64; 3]>,
+}
+
+fn tables() -> &'static SpectralTables {
+    static TABLES: OnceLock<SpectralTables> = OnceLock::new();
+    TABLES.get_or_init(SpectralTables::new)
+}
+
+impl SpectralTables {
+    fn new() -> SpectralTables {
+        let steps = ((LAMBDA_MAX - LAMBDA_MIN) / FIT_STEP) as usize;
+        let fit_lambda: Vec<f64> = (0..=steps)
+            .map(|i| LAMBDA_MIN + i as f64 * FIT_STEP)
+            .collect();
+        let fit_matching: Vec<[f64; 3]> = fit_lambda.iter().map(|&l| cie_xyz(l)).collect();
+
+        let mut integral = [0.0; 3];
+        for matching in &fit_matching {
+            for (c, m) in integral.iter_mut().zip(matching) {
+                *c += m * FIT_STEP;
+            }
+        }
+        let y_integral = integral[1];
+        let white = xyz_to_rgb(integral.map(|c| c / y_integral));
+
+        // Values crowd towards black and full brightness, where the
+        // coefficients change fastest
+        let smoothstep = |x: f64| x * x * (3.0 - 2.0 * x);
+        let z_values =
+            std::array::from_fn(|i| smoothstep(i as f64 / (TABLE_RESOLUTION - 1) as f64));
+
+        let mut tables = SpectralTables {
+            fit_lambda,
+            fit_matching,
+            y_integral,
+            white,
+            z_values,
+            coefficients: vec![[0.0; 3]; 3 * TABLE_RESOLUTION.pow(3)],
+        };
+        tables.fit_all();
+        tables
+    }
+
+    fn index(max_channel: usize, zi: usize, yi: usize, xi: usize) -> usize {
+        ((max_channel * TABLE_RESOLUTION + zi) * TABLE_RESOLUTION + yi) * TABLE_RESOLUTION + xi
+    }
+
+    fn target(max_channel: usize, z: f64, x: f64, y: f64) -> [f64; 3] {
+        let mut rgb = [0.0; 3];
+        rgb[max_channel] = z;
+        rgb[(max_channel + 1) % 3] = x * z;
+        rgb[(max_channel + 2) % 3] = y * z;
+        rgb
+    }
+
+    // Fits every entry, sweeping up and then down in brightness from a dim
+    // start so each fit begins from its neighbor's solution
+    fn fit_all(&mut self) {
+        let n = TABLE_RESOLUTION;
+        let start = n / 5;
+        let ratio = |i: usize| i as f64 / (n - 1) as f64;
+
+        for max_channel in 0..3 {
+            for yi in 0..n {
+                for xi in 0..n {
+                    let mut coefficients = [0.0; 3];
+                    for zi in (start..n).chain((0..start).rev()) {
+                        if zi == start - 1 {
+                            coefficients =
+                                self.coefficients[Self::index(max_channel, start, yi, xi)];
+                        }
+                        let target =
+                            Self::target(max_channel, self.z_values[zi], ratio(xi), ratio(yi));
+                        coefficients = self.fit(target, coefficients);
+                        self.coefficients[Self::index(max_channel, zi, yi, xi)] = coefficients;
+                    }
+                }
+            }
+        }
+    }
+
+    // Color of a sigmoid spectrum reflecting the equal energy illuminant
+    fn color_of(&self, c: &[f64; 3]) -> [f64; 3] {
+        let mut xyz = [0.0; 3];
+        for (&lambda, matching) in self.fit_lambda.iter().zip(&self.fit_matching) {
+            let s = sigmoid_polynomial(c, lambda);
+            for (x, m) in xyz.iter_mut().zip(matching) {
+                *x += s * m * FIT_STEP;
+            }
+        }
+
+        let rgb = xyz_to_rgb(xyz.map(|x| x / self.y_integral));
+        std::array::from_fn(|i| rgb[i] / self.white[i])
+    }
+
+    // Damped Gauss-Newton on the RGB error, backing off steps that make the
+    // fit worse. Colors outside what a sigmoid can reach end up as close as
+    // it gets.
+    fn fit(&self, target: [f64; 3], start: [f64; 3]) -> [f64; 3] {
+        // Pure black and white need infinite coefficients, so aim just inside
+        let target = target.map(|t| t.clamp(1.0e-4, 1.0 - 1.0e-4));
+        let error = |c: &[f64; 3]| {
+            let rgb = self.color_of(c);
+            std::array::from_fn::<f64, 3, _>(|i| rgb[i] - target[i])
+        };
+        let norm = |r: &[f64; 3]| r.iter().map(|x| x * x).sum::<f64>();
+
+        let mut c = start;
+        let mut residual = error(&c);
+        let mut damping = 1.0e-3;
+
+        for _ in 0..FIT_ITERATIONS {
+            if norm(&residual) < 1.0e-10 {
+                break;
+            }
+
+            const H: f64 = 1.0e-5;
+            let mut jacobian = [[0.0; 3]; 3];
+            for j in 0..3 {
+                let mut shifted = c;
+                shifted[j] += H;
+                let r = error(&shifted);
+                for i in 0..3 {
+                    jacobian[i][j] = (r[i] - residual[i]) / H;
+                }
+            }
+
+            // Solve (JᵀJ + λI) Δ = -Jᵀr
+            let mut normal = [[0.0; 3]; 3];
+            let mut rhs = [0.0; 3];
+            for a in 0..3 {
+                for b in 0..3 {
+                    normal[a][b] = (0..3).map(|i| jacobian[i][a] * jacobian[i][b]).sum();
+                }
+                normal[a][a] += damping;
+                rhs[a] = -(0..3).map(|i| jacobian[i][a] * residual[i]).sum::<f64>();
+            }
+            let Some(step) = solve3(normal, rhs) else {
+                break;
+            };
+
+            let candidate = std::array::from_fn(|i| c[i] + step[i]);
+            let candidate_residual = error(&candidate);
+            if norm(&candidate_residual) < norm(&residual) {
+                c = candidate;
+                residual = candidate_residual;
+                damping = f64::max(damping * 0.1, 1.0e-9);
+            } else {
+                damping *= 10.0;
+            }
+        }
+
+        c
+    }
+
+    fn lookup(&self, rgb: [f64; 3]) -> [f64; 3] {
+        let max_channel = if rgb[0] >= rgb[1] && rgb[0] >= rgb[2] {
+            0
+        } else if rgb[1] >= rgb[2] {
+            1
+        } else {
+            2
+        };
+        let z = rgb[max_channel];
+        if z <= 0.0 {
+            return [0.0, 0.0, f64::NEG_INFINITY];
+        }
+        let x = rgb[(max_channel + 1) % 3] / z;
+        let y = rgb[(max_channel + 2) % 3] / z;
+
+        let n = TABLE_RESOLUTION;
+        let scaled = |v: f64| {
+            let v = v.clamp(0.0, 1.0) * (n - 1) as f64;
+            let i = usize::min(v as usize, n - 2);
+            (i, v - i as f64)
+        };
+        let (xi, xf) = scaled(x);
+        let (yi, yf) = scaled(y);
+        let zi = usize::min(self.z_values.partition_point(|&v| v <= z), n - 1).max(1) - 1;
+        let zf =
+            ((z - self.z_values[zi]) / (self.z_values[zi + 1] - self.z_values[zi])).clamp(0.0, 1.0);
+
+        let mut c = [0.0; 3];
+        for (dz, wz) in [(0, 1.0 - zf), (1, zf)] {
+            for (dy, wy) in [(0, 1.0 - yf), (1, yf)] {
+                for (dx, wx) in [(0, 1.0 - xf), (1, xf)] {
+                    let corner =
+                        self.coefficients[Self::index(max_channel, zi + dz, yi + dy, xi + dx)];
+                    for (a, b) in c.iter_mut().zip(corner) {
+                        *a += wz * wy * wx * b;
+                    }
+                }
+            }
+        }
+        c
+    }
+}
+
+// Cramer's rule, or None for a singular system
+fn solve3(m: [[f64; 3]; 3], b: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+
+    let d = det(&m);
+    if d.abs() < 1.0e-30 {
+        return None;
+    }
+
+    Some(std::array::from_fn(|col| {
+        let mut replaced = m;
+        for row in 0..3 {
+            replaced[row][col] = b[row];
+        }
+        det(&replaced) / d
+    }))
+}
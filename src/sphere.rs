@@ -1,9 +1,11 @@
 use std::sync::Arc;
 
+use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::vec3::{self, Point3};
+use crate::utils::PI;
+use crate::vec3::{self, Point3, Vec3};
 
 pub struct Sphere {
     center: Point3,
@@ -21,6 +23,25 @@ impl Sphere {
     }
 }
 
+// Texture coordinates of a point on the unit sphere: u runs around the y
+// axis from x = -1, v from the bottom pole to the top
+fn sphere_uv(p: Point3) -> (f64, f64) {
+    let theta = f64::acos(-p.y());
+    let phi = f64::atan2(-p.z(), p.x()) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+// Direction of increasing u at a point on the unit sphere, running around
+// the y axis. Any horizontal direction will do at the poles.
+fn sphere_tangent(p: Point3) -> Vec3 {
+    let tangent = Vec3::new(p.z(), 0.0, -p.x());
+    if tangent.near_zero() {
+        Vec3::new(1.0, 0.0, 0.0)
+    } else {
+        vec3::unit_vector(tangent)
+    }
+}
+
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let oc = r.origin() - self.center;
@@ -47,10 +68,21 @@ impl Hittable for Sphere {
             p: r.at(root),
             mat: self.mat.clone(),
             normal: Default::default(),
+            tangent: Default::default(),
+            u: Default::default(),
+            v: Default::default(),
             front_face: Default::default(),
+            object_id: Default::default(),
         };
         let outward_normal = (rec.p - self.center) / self.radius;
         rec.set_face_normal(r, outward_normal);
+        (rec.u, rec.v) = sphere_uv(outward_normal);
+        rec.tangent = sphere_tangent(outward_normal);
         Some(rec)
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let radius = Vec3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - radius, self.center + radius))
+    }
 }
@@ -0,0 +1,191 @@
+use std::str::FromStr;
+
+use crate::camera::{Camera, CameraDescription, Projection};
+use crate::color::Color;
+use crate::ray::Ray;
+use crate::utils::{self, PI};
+use crate::vec3::{self, Point3, Vec3};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    // Which way the eye sits along the camera's right vector
+    fn side(self) -> f64 {
+        match self {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        }
+    }
+}
+
+// How the two views are made to agree on the convergence distance
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Convergence {
+    // Both cameras turn towards the convergence point. Simple, but adds
+    // vertical parallax towards the corners of the image.
+    ToeIn,
+    // Parallel cameras whose image windows are shifted to line up at the
+    // convergence distance
+    OffAxis,
+}
+
+impl FromStr for Convergence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Convergence, String> {
+        match s {
+            "toe-in" => Ok(Convergence::ToeIn),
+            "off-axis" => Ok(Convergence::OffAxis),
+            _ => Err(format!("unknown convergence '{}'", s)),
+        }
+    }
+}
+
+// Places a camera per eye, `interocular` scene units apart, with objects at
+// `convergence_distance` appearing at screen depth. Equirectangular cameras
+// become omnidirectional stereo pairs, which converge at infinity.
+#[derive(Clone, Copy)]
+pub struct StereoRig {
+    pub interocular: f64,
+    pub convergence_distance: f64,
+    pub convergence: Convergence,
+}
+
+impl StereoRig {
+    pub fn camera(&self, description: &CameraDescription, eye: Eye) -> Box<dyn Camera> {
+        let (right, forward) = rig_basis(description);
+        let offset = eye.side() * self.interocular / 2.0;
+
+        if description.projection == Projection::Equirectangular {
+            return Box::new(OmniStereoCamera::new(
+                description.lookfrom,
+                description.lookat,
+                description.vup,
+                offset,
+            ));
+        }
+
+        let mut eye_view = description.clone();
+        eye_view.lookfrom = description.lookfrom + offset * right;
+
+        // Parallel orthographic views are identical, so they always toe in
+        let toe_in = self.convergence == Convergence::ToeIn
+            || description.projection == Projection::Orthographic;
+
+        if toe_in {
+            eye_view.lookat = description.lookfrom + self.convergence_distance * forward;
+        } else {
+            eye_view.lookat = description.lookat + offset * right;
+
+            if let Projection::Perspective = description.projection {
+                let theta = utils::degrees_to_radians(description.vfov);
+                let window_width = description.aspect_ratio
+                    * 2.0
+                    * f64::tan(theta / 2.0)
+                    * self.convergence_distance;
+                eye_view.lens_shift.0 -= offset / window_width;
+            }
+        }
+
+        eye_view.build()
+    }
+}
+
+fn rig_basis(description: &CameraDescription) -> (Vec3, Vec3) {
+    let forward = vec3::unit_vector(description.lookat - description.lookfrom);
+    let right = vec3::unit_vector(vec3::cross(forward, description.vup));
+    (right, forward)
+}
+
+// Omnidirectional stereo panorama. Every column is seen from its own point on
+// a circle with the interocular distance as its diameter, so the views stay
+// stereo whichever way the viewer turns.
+pub struct OmniStereoCamera {
+    center: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    offset: f64,
+}
+
+impl OmniStereoCamera {
+    // `offset` is the signed distance of the eye from the center, negative
+    // for the left eye
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, offset: f64) -> OmniStereoCamera {
+        let w = vec3::unit_vector(lookfrom - lookat);
+        let u = vec3::unit_vector(vec3::cross(vup, w));
+        let v = vec3::cross(w, u);
+
+        OmniStereoCamera {
+            center: lookfrom,
+            u,
+            v,
+            w,
+            offset,
+        }
+    }
+}
+
+impl Camera for OmniStereoCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Option<Ray> {
+        let longitude = (s - 0.5) * 2.0 * PI;
+        let latitude = (t - 0.5) * PI;
+
+        let right = longitude.cos() * self.u + longitude.sin() * self.w;
+        let direction = latitude.cos() * (longitude.sin() * self.u - longitude.cos() * self.w)
+            + latitude.sin() * self.v;
+        Some(Ray::new(self.center + self.offset * right, direction))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum StereoLayout {
+    // One image per eye
+    Separate,
+    SideBySide,
+    OverUnder,
+}
+
+impl FromStr for StereoLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StereoLayout, String> {
+        match s {
+            "separate" => Ok(StereoLayout::Separate),
+            "side-by-side" | "sbs" => Ok(StereoLayout::SideBySide),
+            "over-under" | "ou" => Ok(StereoLayout::OverUnder),
+            _ => Err(format!("unknown stereo layout '{}'", s)),
+        }
+    }
+}
+
+// Packs two `width` x `height` views into one image, left eye on the left or
+// on top. Returns the packed image and its size, or None for separate views.
+pub fn pack(
+    layout: StereoLayout,
+    left: &[Color],
+    right: &[Color],
+    width: usize,
+    height: usize,
+) -> Option<(Vec<Color>, usize, usize)> {
+    match layout {
+        StereoLayout::Separate => None,
+        StereoLayout::SideBySide => {
+            let pixels = left
+                .chunks(width)
+                .zip(right.chunks(width))
+                .flat_map(|(l, r)| l.iter().chain(r))
+                .copied()
+                .collect();
+            Some((pixels, 2 * width, height))
+        }
+        StereoLayout::OverUnder => {
+            let pixels = left.iter().chain(right).copied().collect();
+            Some((pixels, width, 2 * height))
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::io;
+
+use crate::color::{self, Color};
+use crate::utils;
+use crate::vec3::{self, Point3, Vec3};
+
+// A color that varies over a surface, looked up by the hit's texture
+// coordinates or its position
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+}
+
+pub struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> SolidColor {
+        SolidColor { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+        self.color
+    }
+}
+
+// Alternating cubes of two colors, `scale` units on a side
+pub struct CheckerTexture {
+    inv_scale: f64,
+    even: Color,
+    odd: Color,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f64, even: Color, odd: Color) -> CheckerTexture {
+        CheckerTexture {
+            inv_scale: 1.0 / scale,
+            even,
+            odd,
+        }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let sum: i64 = (0..3)
+            .map(|i| f64::floor(self.inv_scale * p[i]) as i64)
+            .sum();
+        if sum % 2 == 0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+// A PPM image stretched over the texture coordinates, with v = 0 at the
+// bottom row
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    pub fn load(path: &str) -> io::Result<ImageTexture> {
+        let (width, height, pixels) = color::read_ppm(path)?;
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "texture image is empty",
+            ));
+        }
+
+        Ok(ImageTexture {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
+        let u = u.clamp(0.0, 1.0);
+        let v = 1.0 - v.clamp(0.0, 1.0);
+
+        let i = usize::min((u * self.width as f64) as usize, self.width - 1);
+        let j = usize::min((v * self.height as f64) as usize, self.height - 1);
+        self.pixels[j * self.width + i]
+    }
+}
+
+const PERLIN_POINTS: usize = 256;
+
+// Ken Perlin's gradient noise, with lattice gradients drawn from the
+// calling thread's random generator
+struct Perlin {
+    gradients: Vec<Vec3>,
+    perm: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    fn new() -> Perlin {
+        let gradients = (0..PERLIN_POINTS)
+            .map(|_| vec3::unit_vector(Vec3::random_range(-1.0, 1.0)))
+            .collect();
+
+        Perlin {
+            gradients,
+            perm: [
+                Perlin::permutation(),
+                Perlin::permutation(),
+                Perlin::permutation(),
+            ],
+        }
+    }
+
+    fn permutation() -> Vec<usize> {
+        let mut p: Vec<usize> = (0..PERLIN_POINTS).collect();
+        for i in (1..PERLIN_POINTS).rev() {
+            let target = (utils::random_double() * (i + 1) as f64) as usize;
+            p.swap(i, target.min(i));
+        }
+        p
+    }
+
+    fn noise(&self, p: Point3) -> f64 {
+        let floor = [p.x().floor(), p.y().floor(), p.z().floor()];
+        let frac = [p.x() - floor[0], p.y() - floor[1], p.z() - floor[2]];
+        let cell = floor.map(|f| f as i64);
+
+        // Hermite smoothing of the weights, interpolating the dot products
+        // of the corner gradients with the offsets to them
+        let smooth = frac.map(|f| f * f * (3.0 - 2.0 * f));
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let index = |axis: usize, d: usize| {
+                        (cell[axis] + d as i64).rem_euclid(PERLIN_POINTS as i64) as usize
+                    };
+                    let gradient = self.gradients[self.perm[0][index(0, di)]
+                        ^ self.perm[1][index(1, dj)]
+                        ^ self.perm[2][index(2, dk)]];
+
+                    let offset = Vec3::new(
+                        frac[0] - di as f64,
+                        frac[1] - dj as f64,
+                        frac[2] - dk as f64,
+                    );
+                    let weight = |d: usize, s: f64| if d == 1 { s } else { 1.0 - s };
+                    accum += weight(di, smooth[0])
+                        * weight(dj, smooth[1])
+                        * weight(dk, smooth[2])
+                        * vec3::dot(gradient, offset);
+                }
+            }
+        }
+
+        accum
+    }
+
+    // Sum of `depth` octaves of noise, each twice the frequency and half the
+    // weight of the last
+    fn turbulence(&self, p: Point3, depth: usize) -> f64 {
+        let mut accum = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(p);
+            weight *= 0.5;
+            p = 2.0 * p;
+        }
+
+        accum.abs()
+    }
+}
+
+// Grayscale turbulence, `scale` times finer than the scene's units
+pub struct NoiseTexture {
+    noise: Perlin,
+    scale: f64,
+}
+
+impl NoiseTexture {
+    pub fn new(scale: f64) -> NoiseTexture {
+        NoiseTexture {
+            noise: Perlin::new(),
+            scale,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let t = f64::min(self.noise.turbulence(self.scale * p, 7), 1.0);
+        Color::new(t, t, t)
+    }
+}
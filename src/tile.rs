@@ -0,0 +1,150 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+// A rectangle of pixels [x0, x1) x [y0, y1), with rows counted from the top
+#[derive(Clone, Copy)]
+pub struct Tile {
+    pub index: usize,
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn pixel_count(&self) -> usize {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TileOrder {
+    Scanline,
+    Spiral,
+    Hilbert,
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TileOrder, String> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!("unknown tile order '{}'", s)),
+        }
+    }
+}
+
+impl Display for TileOrder {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let name = match self {
+            TileOrder::Scanline => "scanline",
+            TileOrder::Spiral => "spiral",
+            TileOrder::Hilbert => "hilbert",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Splits the image into square tiles, listed in the order they should be
+// handed out to render threads. Panics if `tile_size` is zero.
+pub fn generate_tiles(
+    width: usize,
+    height: usize,
+    tile_size: usize,
+    order: TileOrder,
+) -> Vec<Tile> {
+    assert!(tile_size > 0, "tile size must be at least one pixel");
+    let tiles_x = width.div_ceil(tile_size);
+    let tiles_y = height.div_ceil(tile_size);
+
+    let coords = match order {
+        TileOrder::Scanline => (0..tiles_y)
+            .flat_map(|ty| (0..tiles_x).map(move |tx| (tx, ty)))
+            .collect(),
+        TileOrder::Spiral => spiral_order(tiles_x, tiles_y),
+        TileOrder::Hilbert => hilbert_order(tiles_x, tiles_y),
+    };
+
+    coords
+        .into_iter()
+        .enumerate()
+        .map(|(index, (tx, ty))| Tile {
+            index,
+            x0: tx * tile_size,
+            y0: ty * tile_size,
+            x1: usize::min((tx + 1) * tile_size, width),
+            y1: usize::min((ty + 1) * tile_size, height),
+        })
+        .collect()
+}
+
+// Walks outwards from the center tile in a square spiral
+fn spiral_order(tiles_x: usize, tiles_y: usize) -> Vec<(usize, usize)> {
+    let total = tiles_x * tiles_y;
+    let mut coords = Vec::with_capacity(total);
+
+    let (mut x, mut y) = ((tiles_x as i64 - 1) / 2, (tiles_y as i64 - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut direction = 0;
+    let mut run = 1;
+
+    let visit = |x: i64, y: i64, coords: &mut Vec<(usize, usize)>| {
+        if x >= 0 && y >= 0 && x < tiles_x as i64 && y < tiles_y as i64 {
+            coords.push((x as usize, y as usize));
+        }
+    };
+
+    visit(x, y, &mut coords);
+    while coords.len() < total {
+        for _ in 0..2 {
+            let (dx, dy) = directions[direction];
+            for _ in 0..run {
+                x += dx;
+                y += dy;
+                visit(x, y, &mut coords);
+            }
+            direction = (direction + 1) % 4;
+        }
+        run += 1;
+    }
+
+    coords
+}
+
+// Follows a Hilbert curve over the smallest power-of-two grid covering the
+// tiles, skipping cells that fall outside the image
+fn hilbert_order(tiles_x: usize, tiles_y: usize) -> Vec<(usize, usize)> {
+    let n = usize::max(tiles_x, tiles_y).next_power_of_two();
+
+    (0..n * n)
+        .map(|d| hilbert_d2xy(n, d))
+        .filter(|&(x, y)| x < tiles_x && y < tiles_y)
+        .collect()
+}
+
+fn hilbert_d2xy(n: usize, d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+
+    (x, y)
+}
@@ -0,0 +1,216 @@
+use crate::aabb::{self, Aabb};
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::utils;
+use crate::vec3::{self, Point3, Vec3};
+
+// Uniform scale, then rotation about the x, y and z axes (in degrees), then
+// translation
+#[derive(Clone, Copy)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Vec3,
+    pub scale: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Vec3::new(0.0, 0.0, 0.0),
+            scale: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    pub fn is_identity(&self) -> bool {
+        self.translation.near_zero() && self.rotation.near_zero() && self.scale == 1.0
+    }
+
+    // Blends each component linearly, `u` running from 0 at `self` to 1 at
+    // `other`
+    pub fn lerp(&self, other: &Transform, u: f64) -> Transform {
+        Transform {
+            translation: (1.0 - u) * self.translation + u * other.translation,
+            rotation: (1.0 - u) * self.rotation + u * other.rotation,
+            scale: (1.0 - u) * self.scale + u * other.scale,
+        }
+    }
+
+    // Rows of the rotation matrix
+    fn rotation_matrix(&self) -> [Vec3; 3] {
+        let (sx, cx) = utils::degrees_to_radians(self.rotation.x()).sin_cos();
+        let (sy, cy) = utils::degrees_to_radians(self.rotation.y()).sin_cos();
+        let (sz, cz) = utils::degrees_to_radians(self.rotation.z()).sin_cos();
+
+        // Rz * Ry * Rx
+        [
+            Vec3::new(cz * cy, cz * sy * sx - sz * cx, cz * sy * cx + sz * sx),
+            Vec3::new(sz * cy, sz * sy * sx + cz * cx, sz * sy * cx - cz * sx),
+            Vec3::new(-sy, cy * sx, cy * cx),
+        ]
+    }
+}
+
+// A transform with its rotation matrix worked out
+struct Placement {
+    transform: Transform,
+    rows: [Vec3; 3],
+}
+
+impl Placement {
+    fn new(transform: Transform) -> Placement {
+        Placement {
+            transform,
+            rows: transform.rotation_matrix(),
+        }
+    }
+
+    fn rotate(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            vec3::dot(self.rows[0], v),
+            vec3::dot(self.rows[1], v),
+            vec3::dot(self.rows[2], v),
+        )
+    }
+
+    // The transpose of an orthonormal matrix is its inverse
+    fn unrotate(&self, v: Vec3) -> Vec3 {
+        v.x() * self.rows[0] + v.y() * self.rows[1] + v.z() * self.rows[2]
+    }
+
+    fn to_world(&self, p: Point3) -> Point3 {
+        self.rotate(self.transform.scale * p) + self.transform.translation
+    }
+
+    // Hit distances are preserved because the direction is scaled along with
+    // the origin
+    fn hit(&self, object: &dyn Hittable, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let scale = self.transform.scale;
+        let origin = self.unrotate(r.origin() - self.transform.translation) / scale;
+        let direction = self.unrotate(r.direction()) / scale;
+        let local = r.spawn(origin, direction);
+
+        let mut rec = object.hit(&local, t_min, t_max)?;
+        rec.p = self.to_world(rec.p);
+        rec.normal = self.rotate(rec.normal);
+        rec.tangent = self.rotate(rec.tangent);
+        Some(rec)
+    }
+
+    fn bounding_box(&self, bounds: &Aabb) -> Aabb {
+        let corners = bounds.corners().map(|corner| self.to_world(corner));
+
+        corners[1..]
+            .iter()
+            .fold(Aabb::new(corners[0], corners[0]), |b, &c| {
+                aabb::surrounding_box(&b, &Aabb::new(c, c))
+            })
+    }
+}
+
+// An object placed in the scene by a transform
+pub struct Transformed {
+    object: Box<dyn Hittable>,
+    placement: Placement,
+}
+
+impl Transformed {
+    pub fn new(object: Box<dyn Hittable>, transform: Transform) -> Transformed {
+        Transformed {
+            object,
+            placement: Placement::new(transform),
+        }
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.placement.hit(self.object.as_ref(), r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bounds = self.object.bounding_box()?;
+        Some(self.placement.bounding_box(&bounds))
+    }
+}
+
+// Steps the motion is sampled at when bounding a moving object
+const MOTION_BOUND_STEPS: usize = 8;
+
+// An object moving from one transform to another while the shutter is open,
+// placed for each ray by the ray's time
+pub struct MotionTransformed {
+    object: Box<dyn Hittable>,
+    open: Transform,
+    close: Transform,
+    shutter: f64,
+}
+
+impl MotionTransformed {
+    // `open` and `close` place the object when the shutter opens and when it
+    // closes `shutter` seconds later
+    pub fn new(
+        object: Box<dyn Hittable>,
+        open: Transform,
+        close: Transform,
+        shutter: f64,
+    ) -> MotionTransformed {
+        MotionTransformed {
+            object,
+            open,
+            close,
+            shutter,
+        }
+    }
+
+    fn placement(&self, u: f64) -> Placement {
+        Placement::new(self.open.lerp(&self.close, u))
+    }
+}
+
+impl Hittable for MotionTransformed {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let u = if self.shutter > 0.0 {
+            (r.time() / self.shutter).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        self.placement(u).hit(self.object.as_ref(), r, t_min, t_max)
+    }
+
+    // Bounds the object at evenly spaced points of its motion. Between steps
+    // a point can stray from where it was at the nearest one by half a step
+    // at its top speed, so each box is padded by that much, covering the
+    // arcs rotations sweep out between the steps.
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bounds = self.object.bounding_box()?;
+
+        // Top speed of any point of the object per unit of motion: the
+        // translation, plus the scaling and spinning of the point farthest
+        // from the origin. Turning about the three axes at once spins no
+        // faster than the sum of their rates.
+        let reach = bounds
+            .corners()
+            .iter()
+            .map(|corner| corner.length())
+            .fold(0.0, f64::max);
+        let turn = self.close.rotation - self.open.rotation;
+        let spin = utils::degrees_to_radians(turn.x().abs() + turn.y().abs() + turn.z().abs());
+        let scale = f64::max(self.open.scale.abs(), self.close.scale.abs());
+        let speed = (self.close.translation - self.open.translation).length()
+            + ((self.close.scale - self.open.scale).abs() + scale * spin) * reach;
+        let pad = 0.5 * speed / MOTION_BOUND_STEPS as f64;
+        let pad = Vec3::new(pad, pad, pad);
+
+        (0..=MOTION_BOUND_STEPS)
+            .map(|i| {
+                let b = self
+                    .placement(i as f64 / MOTION_BOUND_STEPS as f64)
+                    .bounding_box(&bounds);
+                Aabb::new(b.min() - pad, b.max() + pad)
+            })
+            .reduce(|a, b| aabb::surrounding_box(&a, &b))
+    }
+}
@@ -1,15 +1,49 @@
-use rand::Rng;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+use std::cell::RefCell;
 
 pub use std::f64::consts::PI;
 
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_rng(&mut rand::rng()));
+}
+
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
 pub fn random_double() -> f64 {
+    RNG.with(|rng| rng.borrow_mut().random())
+}
+
+// Reseeds the calling thread's generator, making the numbers that follow
+// reproducible
+pub fn seed_random(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
+pub fn random_seed() -> u64 {
     rand::rng().random()
 }
 
+// Derives an independent seed for one camera sample, so each sample is
+// reproducible regardless of which thread or pass renders it
+pub fn sample_seed(seed: u64, pixel: usize, sample: u32) -> u64 {
+    let mut h = seed;
+    for value in [pixel as u64, sample as u64] {
+        h = splitmix64(h ^ value.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    }
+    h
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
 pub fn random_double_range(min: f64, max: f64) -> f64 {
     min + (max - min) * random_double()
 }
@@ -1,7 +1,7 @@
 use crate::utils;
 
 use std::fmt::{Display, Formatter, Result};
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub};
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, Mul, MulAssign, Neg, Sub};
 
 #[derive(Clone, Copy, Default)]
 pub struct Vec3 {
@@ -49,6 +49,10 @@ impl Vec3 {
         self.e[0] * self.e[0] + self.e[1] * self.e[1] + self.e[2] * self.e[2]
     }
 
+    pub fn max_component(&self) -> f64 {
+        f64::max(self.e[0], f64::max(self.e[1], self.e[2]))
+    }
+
     pub fn near_zero(&self) -> bool {
         const EPS: f64 = 1.0e-8;
         self.e[0].abs() < EPS && self.e[1].abs() < EPS && self.e[2].abs() < EPS
@@ -71,6 +75,14 @@ impl Neg for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, i: usize) -> &f64 {
+        &self.e[i]
+    }
+}
+
 impl AddAssign for Vec3 {
     fn add_assign(&mut self, v: Vec3) {
         *self = *self + v;